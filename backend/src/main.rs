@@ -1,11 +1,12 @@
 use ic_cdk::api::{caller, time};
-use ic_cdk::export::candid::{CandidType, Decode, Deserialize, Principal};
+use ic_cdk::export::candid::{CandidType, Decode, Deserialize, Int, Nat, Principal};
 use ic_cdk::storage::{stable_restore, stable_save};
 use ic_cdk_macros::{post_upgrade, pre_upgrade, query, update};
 use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     convert::TryFrom,
 };
 
@@ -21,14 +22,90 @@ const MIN_DURATION: std::time::Duration = std::time::Duration::from_secs(60); //
 // The maximum duration that a task can stay open.
 const MAX_DURATION: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 24); // 1 day
 
-// The maximum size of the payload of a task.
-const MAX_TASK_PAYLOAD: usize = 10 * 1024; // 100 KiB
+// The maximum size of the payload of a task. Image tasks may carry a source
+// image of up to `MAX_IMAGE_SIZE` on top.
+const MAX_TASK_PAYLOAD: usize = 10 * 1024; // 10 KiB
 
 // The maximum number of answers that can be provided per task.
 const MAX_NUMBER_ANSWERS: usize = 10;
 
 // The maximum size of an answer's content.
-const MAX_CONTENT_SIZE: usize = 10 * 1024; // 100 KiB
+const MAX_CONTENT_SIZE: usize = 10 * 1024; // 10 KiB
+
+// The maximum size of an image, both as the source of a task and as an answer.
+const MAX_IMAGE_SIZE: usize = 1024 * 1024; // 1 MiB
+
+// The maximum width or height of an image that can be requested.
+const MAX_IMAGE_DIMENSION: u32 = 10_000;
+
+// The maximum number of transactions returned by a single query.
+const MAX_TRANSACTIONS_PER_PAGE: u64 = 100;
+
+// The maximum number of tasks returned by a single query.
+const MAX_TASKS_PER_PAGE: u64 = 100;
+
+// The number of characters of a task's text shown in its summary.
+const PAYLOAD_PREVIEW_LENGTH: usize = 80;
+
+// The instructions a heartbeat may spend on due tasks before it leaves the
+// rest for the following rounds. The task that crosses the budget is still
+// processed, so this stays well below the per-message instruction limit.
+const HEARTBEAT_INSTRUCTION_BUDGET: u64 = 1_000_000_000;
+
+// The reputation gained by the author of an answer that receives a payout.
+const REPUTATION_FOR_PAYOUT: i64 = 10;
+
+// The reputation lost by the author of an answer with negative net votes.
+const REPUTATION_FOR_REJECTED_ANSWER: i64 = 5;
+
+// The reputation gained by voting yes on the top-ranked answer of a task.
+const REPUTATION_FOR_WINNING_VOTE: i64 = 1;
+
+// The maximum number of entries returned by `get_leaderboard`.
+const MAX_LEADERBOARD_SIZE: u64 = 100;
+
+// The time after settlement during which the outcome of a task can be
+// disputed. Payouts are held in escrow until then.
+const DISPUTE_WINDOW: std::time::Duration = std::time::Duration::from_secs(60 * 60); // 1 hour
+
+// The tokens locked when opening a dispute.
+const DISPUTE_BOND: Amount = 10;
+
+// The maximum size of the reason given for a dispute.
+const MAX_DISPUTE_REASON: usize = 1024;
+
+// The number of jurors drawn for a dispute.
+const JURY_SIZE: usize = 5;
+
+// The minimum reputation of a juror.
+const MIN_JUROR_REPUTATION: i64 = 20;
+
+// The time jurors have to deliver their verdicts.
+const JURY_PERIOD: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 24); // 1 day
+
+// The maximum size of a transfer memo.
+const MAX_MEMO_SIZE: usize = 32;
+
+// The size of a sealed vote, a SHA-256 hash.
+const COMMITMENT_SIZE: usize = 32;
+
+// The size of an ICRC-1 subaccount.
+const SUBACCOUNT_SIZE: usize = 32;
+
+// The first byte of the canister-owned subaccounts holding a task's escrow.
+const ESCROW_SUBACCOUNT_TAG: u8 = 1;
+
+// The ICRC-1 metadata of the butler token.
+const TOKEN_NAME: &str = "IC Butler Token";
+const TOKEN_SYMBOL: &str = "BTLR";
+const TOKEN_DECIMALS: u8 = 0;
+const TRANSFER_FEE: Amount = 0;
+
+// The window in which transfers with a `created_at_time` are deduplicated.
+const TX_WINDOW: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 24); // 1 day
+
+// The allowed clock drift between the ledger and its clients.
+const PERMITTED_DRIFT: std::time::Duration = std::time::Duration::from_secs(60 * 2); // 2 minutes
 
 type AnswerId = u64;
 type Content = ByteBuf;
@@ -37,23 +114,54 @@ type TaskId = u64;
 type TaskPayload = ByteBuf;
 type Timestamp = u64;
 type Amount = u64;
+type BlockIndex = u64;
+type Memo = ByteBuf;
+type Subaccount = ByteBuf;
 
 struct State {
     next_task_id: RefCell<TaskId>,
-    tasks: RefCell<HashMap<TaskId, TaskInternal>>,
+    // Ordered by ID so that tasks can be listed newest first.
+    tasks: RefCell<BTreeMap<TaskId, TaskInternal>>,
     answers: RefCell<HashMap<AnswerId, Answer>>,
     next_answer_id: RefCell<AnswerId>,
-    ledger: RefCell<HashMap<Principal, Amount>>,
+    ledger: RefCell<HashMap<Account, Amount>>,
+    // The principals that received the registration grant. Anyone can hold
+    // tokens, so this is tracked apart from the ledger.
+    registered: RefCell<HashSet<Principal>>,
+    transactions: RefCell<TransactionLog>,
+    // The open and in-review tasks by the time of their next phase change,
+    // used by the heartbeat to find the tasks to move into review or settle.
+    // It is derived from `tasks` and not persisted across upgrades.
+    deadlines: RefCell<BTreeMap<Timestamp, Vec<TaskId>>>,
+    // The task of every answer, derived from `tasks` like `deadlines`.
+    answer_tasks: RefCell<HashMap<AnswerId, TaskId>>,
+    // The tasks each principal submitted, the answers they wrote and the
+    // answers they voted on. These are derived from `tasks` and `answers`
+    // and not persisted either.
+    submitted_tasks: RefCell<HashMap<Principal, BTreeSet<TaskId>>>,
+    authored_answers: RefCell<HashMap<Principal, BTreeSet<AnswerId>>>,
+    voted_answers: RefCell<HashMap<Principal, BTreeSet<AnswerId>>>,
+    reputation: RefCell<HashMap<Principal, i64>>,
+    skills: RefCell<HashMap<Principal, HashMap<Skill, i64>>>,
 }
 
 impl Default for State {
     fn default() -> Self {
         State {
             next_task_id: RefCell::new(0),
-            tasks: RefCell::new(HashMap::default()),
+            tasks: RefCell::new(BTreeMap::new()),
             answers: RefCell::new(HashMap::default()),
             next_answer_id: RefCell::new(0),
             ledger: RefCell::new(HashMap::default()),
+            registered: RefCell::new(HashSet::default()),
+            transactions: RefCell::new(TransactionLog::default()),
+            deadlines: RefCell::new(BTreeMap::new()),
+            answer_tasks: RefCell::new(HashMap::default()),
+            submitted_tasks: RefCell::new(HashMap::default()),
+            authored_answers: RefCell::new(HashMap::default()),
+            voted_answers: RefCell::new(HashMap::default()),
+            reputation: RefCell::new(HashMap::default()),
+            skills: RefCell::new(HashMap::default()),
         }
     }
 }
@@ -70,7 +178,7 @@ enum Choice {
     No,
 }
 
-#[derive(Clone, Debug, CandidType, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, CandidType, Deserialize)]
 enum Language {
     #[serde(rename = "chinese")]
     Chinese,
@@ -86,7 +194,7 @@ enum Language {
     Italian,
 }
 
-#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, CandidType, Deserialize)]
 enum TaskType {
     #[serde(rename = "translate_text")]
     TranslateText,
@@ -96,709 +204,7363 @@ enum TaskType {
 
 #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
 enum TaskStatus {
+    // The task takes answers until its answer deadline.
     #[serde(rename = "open")]
     Open,
+    // The task takes votes until its vote deadline, followed by the reveal
+    // period with commit-reveal voting.
+    #[serde(rename = "in_review")]
+    InReview,
+    // The task has been settled. Unless an answer was accepted, its payouts
+    // are held in escrow until the dispute deadline.
     #[serde(rename = "closed")]
     Closed,
+    // The outcome of the task is being reviewed by a jury.
+    #[serde(rename = "disputed")]
+    Disputed,
+    #[serde(rename = "cancelled")]
+    Cancelled,
 }
 
-#[derive(Clone, Debug, CandidType, Deserialize)]
-struct TranslateTextInput {
-    input: String,
-    language: Language,
+// An area in which answer authors build up a skill score, in addition to
+// their global reputation.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, CandidType, Deserialize)]
+enum Skill {
+    #[serde(rename = "language")]
+    Language(Language),
+    #[serde(rename = "task_type")]
+    TaskType(TaskType),
 }
 
 #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
-struct Answer {
-    submitter: Principal,
-    submission_time: Timestamp,
-    content: Content,
-    votes: Vec<Vote>,
+struct SkillScore {
+    skill: Skill,
+    score: i64,
 }
 
+// Determines whether a task that already has answers can be cancelled.
 #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
-struct Task {
-    submitter: Principal,
-    task_type: TaskType,
-    payload: TaskPayload,
-    deadline: Timestamp,
-    reward: Amount,
-    answers: Vec<AnswerWithId>,
-    status: TaskStatus,
+enum CancellationPolicy {
+    // The task can only be cancelled while nobody has answered it.
+    #[serde(rename = "without_answers")]
+    WithoutAnswers,
+    // The task can be cancelled while open and the given percentage of the
+    // reward is split equally among the answerers.
+    #[serde(rename = "compensate_answerers")]
+    CompensateAnswerers { percent: u8 },
 }
 
+// Determines how the reward of a task is split among its answers. Answers
+// are ranked by net votes and ties go to the earliest submission.
 #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
-struct ShortTask {
-    id: TaskId,
-    submitter: Principal,
-    task_type: TaskType,
-    status: TaskStatus,
+enum PayoutScheme {
+    // The top-ranked answer gets the whole reward.
+    #[serde(rename = "winner_takes_all")]
+    WinnerTakesAll,
+    // The answer ranked i-th gets the i-th percentage of the reward. The
+    // percentages must add up to 100.
+    #[serde(rename = "top_n")]
+    TopN(Vec<u8>),
+    // The answers with positive net votes split the reward in proportion to
+    // their net votes.
+    #[serde(rename = "proportional")]
+    Proportional,
+}
+
+impl PayoutScheme {
+    fn validate(&self) -> Result<(), ButlerError> {
+        if let PayoutScheme::TopN(percentages) = self {
+            if percentages.is_empty() || percentages.len() > MAX_NUMBER_ANSWERS {
+                return Err(ButlerError::InvalidOptions(format!(
+                    "Between 1 and {} payout percentages are required, but {} were given.",
+                    MAX_NUMBER_ANSWERS,
+                    percentages.len()
+                )));
+            }
+            let total: u32 = percentages.iter().map(|p| *p as u32).sum();
+            if total != 100 {
+                return Err(ButlerError::InvalidOptions(format!(
+                    "Payout percentages must add up to 100, but they add up to {}.",
+                    total
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 
+// Determines how much each vote counts when the task is settled.
 #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
-struct Vote {
-    voter: Principal,
-    choice: Choice,
+enum VoteWeighting {
+    // Every vote counts as one.
+    #[serde(rename = "equal")]
+    Equal,
+    // Votes count as many tokens as the voter locks in the task's escrow
+    // until the task is settled.
+    #[serde(rename = "stake")]
+    Stake,
+    // Votes count as one plus the voter's reputation, if positive, at the
+    // time of voting.
+    #[serde(rename = "reputation")]
+    Reputation,
 }
 
-#[derive(Clone, Debug, CandidType, Deserialize)]
-struct TaskInternal {
-    submitter: Principal,
-    task_type: TaskType,
-    payload: TaskPayload,
-    deadline: Timestamp,
-    reward: Amount,
-    answers: HashSet<AnswerId>,
-    status: TaskStatus,
+// With commit-reveal voting, votes are sealed until the vote deadline and
+// revealed during the following `reveal_period`. Only revealed votes count.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct CommitReveal {
+    reveal_period: Duration,
+    // The deposit locked with every sealed vote. It is returned when the
+    // vote is revealed and burned otherwise.
+    penalty: Amount,
+}
+
+impl CommitReveal {
+    fn validate(&self) -> Result<(), ButlerError> {
+        validate_period("reveal period", self.reveal_period)
+    }
 }
 
+// Determines how the outcome of a task is decided.
 #[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
-struct AnswerWithId {
-    answer_id: AnswerId,
-    answer: Answer,
+enum SettlementMode {
+    // The answers are ranked by votes when the task is settled.
+    #[serde(rename = "vote")]
+    Vote,
+    // The submitter accepts an answer, at the latest `grace_period` after
+    // the answer deadline. Otherwise, the task is settled by votes.
+    #[serde(rename = "submitter_accepts")]
+    SubmitterAccepts { grace_period: Duration },
 }
 
+impl SettlementMode {
+    fn validate(&self, review_period: Duration) -> Result<(), ButlerError> {
+        if let SettlementMode::SubmitterAccepts { grace_period } = self {
+            validate_period("grace period", *grace_period)?;
+            if *grace_period > review_period {
+                return Err(ButlerError::InvalidOptions(
+                    "The grace period cannot be longer than the review period.".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
 
-#[update]
-fn register() {
-    let caller = caller();
+// Tokens set aside by the submitter for the voters who voted yes on the
+// winning answer, split by vote weight. Voters who voted no on it lose
+// `slash_percent` of their stake to the pool.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct VoterPool {
+    amount: Amount,
+    slash_percent: u8,
+}
 
-    STATE.with(|s| {
-        let mut ledger = s.ledger.borrow_mut();
-        if ledger.contains_key(&caller) {
-            ic_cdk::trap(&format!("{} has already registered.", caller));
+impl VoterPool {
+    fn validate(&self, vote_weighting: &VoteWeighting) -> Result<(), ButlerError> {
+        if self.amount == 0 {
+            return Err(ButlerError::InvalidAmount);
         }
-        ledger.insert(caller, INITIAL_TOKENS);
-    });
+        if self.slash_percent > 100 {
+            return Err(ButlerError::InvalidOptions(format!(
+                "Slashing must be at most 100 percent of a stake, but {} was given.",
+                self.slash_percent
+            )));
+        }
+        if self.slash_percent > 0 && *vote_weighting != VoteWeighting::Stake {
+            return Err(ButlerError::InvalidOptions(
+                "Only stakes can be slashed, so slashing requires votes weighted by stake."
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
 }
 
-#[update]
-fn submit_task(
-    task_type: TaskType,
-    payload: TaskPayload,
-    duration: Duration,
-    reward: Amount,
-) -> TaskId {
-    let caller = caller();
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct VoterReward {
+    voter: Principal,
+    amount: Amount,
+}
 
-    STATE.with(|s| {
-        let ledger = s.ledger.borrow();
-        match ledger.get(&caller) {
-            Some(balance) => {
-                if *balance < SUBMISSION_COST {
-                    ic_cdk::trap(&format!(
-                        "{} has only {} tokens but {} are needed to submit a task.",
-                        caller, balance, SUBMISSION_COST
-                    ));
-                }
-            }
-            None => {
-                ic_cdk::trap(&format!("{} has not been registered yet.", caller));
-            }
+// What a quorum counts: all votes, or the distinct principals who voted.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum QuorumCount {
+    #[serde(rename = "votes")]
+    Votes,
+    #[serde(rename = "voters")]
+    Voters,
+}
+
+// What happens to a task that ends its review below quorum.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum QuorumFallback {
+    // The review phase is extended once by its original length. If the
+    // quorum is still not met, the reward is refunded.
+    #[serde(rename = "extend_review")]
+    ExtendReview,
+    #[serde(rename = "refund")]
+    Refund,
+}
+
+// The participation required for a task to be settled by votes.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Quorum {
+    min_count: u64,
+    count: QuorumCount,
+    fallback: QuorumFallback,
+}
+
+impl Quorum {
+    fn validate(&self) -> Result<(), ButlerError> {
+        if self.min_count == 0 {
+            return Err(ButlerError::InvalidOptions(
+                "A quorum requires at least one vote.".to_string(),
+            ));
         }
-    });
+        Ok(())
+    }
 
-    match task_type {
-        TaskType::TranslateText => {
-            if payload.len() > MAX_TASK_PAYLOAD {
-                ic_cdk::trap(&format!(
-                    "Maximum size of payload is {} but {} was given.",
-                    MAX_TASK_PAYLOAD,
-                    payload.len()
-                ));
-            }
+    fn is_met(&self, answers: &[(AnswerId, &Answer)]) -> bool {
+        let votes = answers.iter().flat_map(|(_, answer)| answer.votes.iter());
+        let count = match self.count {
+            QuorumCount::Votes => votes.count(),
+            QuorumCount::Voters => votes.map(|vote| vote.voter).collect::<HashSet<_>>().len(),
+        };
+        count as u64 >= self.min_count
+    }
+}
 
-            if let Err(err) = Decode!(&payload, TranslateTextInput) {
-                ic_cdk::trap(&format!("Invalid input for tranlating text: {}", err));
-            };
+// Who is barred from voting on a task, on top of answer authors who can
+// never vote on their own answers.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+struct ConflictOfInterest {
+    // Answer authors cannot vote on any answer to the task.
+    exclude_answerers: bool,
+    exclude_submitter: bool,
+}
 
-            if duration < u64::try_from(MIN_DURATION.as_nanos()).unwrap() {
-                ic_cdk::trap(&format!(
-                    "Mininum duration for task is {:?}, but {:?} was given",
-                    MIN_DURATION,
-                    std::time::Duration::from_nanos(duration)
-                ));
-            }
+// Why a task was closed or cancelled.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum CloseReason {
+    // The task was settled by votes.
+    #[serde(rename = "votes")]
+    Votes,
+    // The submitter accepted an answer.
+    #[serde(rename = "accepted")]
+    Accepted,
+    // The review ended below quorum and the reward was refunded.
+    #[serde(rename = "quorum_not_reached")]
+    QuorumNotReached,
+    // Nobody voted on any answer and the reward was refunded.
+    #[serde(rename = "no_votes")]
+    NoVotes,
+    #[serde(rename = "cancelled")]
+    Cancelled,
+}
 
-            if duration > u64::try_from(MAX_DURATION.as_nanos()).unwrap() {
-                ic_cdk::trap(&format!(
-                    "Maximum duration for task is {:?}, but {:?} was given",
-                    MAX_DURATION,
-                    std::time::Duration::from_nanos(duration)
-                ));
-            }
+// A juror's verdict on a disputed task: either the settled outcome stands or
+// the whole reward goes to the given answer.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum Verdict {
+    #[serde(rename = "uphold")]
+    Uphold,
+    #[serde(rename = "award")]
+    Award(AnswerId),
+}
 
-            STATE.with(|s| {
-                let mut ledger = s.ledger.borrow_mut();
-                // Safe because we have checked that the caller is registered above.
-                let balance = *ledger.get(&caller).unwrap();
-                if balance < SUBMISSION_COST + reward {
-                    ic_cdk::trap(&format!(
-                        "{} has only {} tokens but {} were requested as a reward.",
-                        caller,
-                        balance - SUBMISSION_COST,
-                        reward
-                    ));
-                }
-                ledger.insert(caller, balance - SUBMISSION_COST - reward);
-            });
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct JurorVerdict {
+    juror: Principal,
+    verdict: Verdict,
+}
 
-            let task_id = STATE.with(|s| s.next_task_id.replace_with(|&mut old| old + 1));
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Dispute {
+    opened_by: Principal,
+    reason: String,
+    bond: Amount,
+    opened_at: Timestamp,
+    jury: Vec<Principal>,
+    verdicts: Vec<JurorVerdict>,
+    // The dispute is resolved once all jurors have delivered their verdict,
+    // or with the verdicts delivered by then.
+    deadline: Timestamp,
+    outcome: Option<Verdict>,
+}
 
-            STATE.with(|s| {
-                let mut tasks = s.tasks.borrow_mut();
-                tasks.insert(
-                    task_id,
-                    TaskInternal {
-                        submitter: caller,
-                        task_type,
-                        payload,
-                        deadline: time() + duration,
-                        reward,
-                        answers: HashSet::new(),
-                        status: TaskStatus::Open,
-                    },
-                );
-            });
+// Determines who can see the contents of the answers to a task.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum AnswerVisibility {
+    // Answers are visible to everybody as soon as they are submitted.
+    #[serde(rename = "public")]
+    Public,
+    // Until the answer deadline, answers are only visible to the task
+    // submitter and their authors.
+    #[serde(rename = "hidden")]
+    Hidden,
+}
 
-            task_id
-        }
-        TaskType::EditImage => ic_cdk::trap("Edit image use case is unimplemented!"),
+// Checks that an optional phase of a task lasts between `MIN_DURATION` and
+// `MAX_DURATION`.
+fn validate_period(name: &str, period: Duration) -> Result<(), ButlerError> {
+    let min_duration = u64::try_from(MIN_DURATION.as_nanos()).unwrap();
+    let max_duration = u64::try_from(MAX_DURATION.as_nanos()).unwrap();
+    if period < min_duration || period > max_duration {
+        return Err(ButlerError::InvalidOptions(format!(
+            "The {} must be between {} and {} nanoseconds, but it is {}.",
+            name, min_duration, max_duration, period
+        )));
     }
+    Ok(())
 }
 
-#[query]
-fn get_task(id: TaskId) -> Task {
-    let caller = caller();
-    get_task_impl(caller, id)
+// Optional settings of a task, chosen at submission time.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+struct TaskOptions {
+    cancellation_policy: Option<CancellationPolicy>,
+    payout_scheme: Option<PayoutScheme>,
+    vote_weighting: Option<VoteWeighting>,
+    // The skill score required to answer the task, in the task's language
+    // for translations and in the task's type otherwise.
+    min_skill_score: Option<i64>,
+    commit_reveal: Option<CommitReveal>,
+    answer_visibility: Option<AnswerVisibility>,
+    // The duration of the review phase, which follows the submission phase.
+    // Defaults to the duration of the submission phase.
+    review_period: Option<Duration>,
+    settlement_mode: Option<SettlementMode>,
+    voter_pool: Option<VoterPool>,
+    quorum: Option<Quorum>,
+    conflict_of_interest: Option<ConflictOfInterest>,
 }
 
-fn get_task_impl(caller: Principal, id: TaskId) -> Task {
-    STATE.with(|s| {
-        let ledger = s.ledger.borrow();
-        if !ledger.contains_key(&caller) {
-            ic_cdk::trap(&format!("{} has not been registered yet.", caller));
-        }
-        let task_map = s.tasks.borrow();
-        let answers_map = s.answers.borrow();
-        match task_map.get(&id) {
-            Some(task_internal_ref) => {
-                let task_internal: TaskInternal = (*task_internal_ref).clone();
-                let mut answers = Vec::new();
-                for ans_id in task_internal.answers.iter() {
-                    match answers_map.get(ans_id) {
-                        Some(ans_ref) => {
-                            answers.push(AnswerWithId{
-                                answer_id: *ans_id,
-                                answer: (*ans_ref).clone(),
-                            })
-                        }
-                        None => {
-                            ic_cdk::trap(&format!(
-                                "Inconsistent state. AnswerId {} cannot be found",
-                                ans_id
-                            ));
-                        }
-                    }
-                }
-                Task {
-                    submitter: task_internal.submitter,
-                    task_type: task_internal.task_type,
-                    payload: task_internal.payload,
-                    deadline: task_internal.deadline,
-                    reward: task_internal.reward,
-                    answers,
-                    status: task_internal.status,
-                }
-            }
-            None => {
-                ic_cdk::trap(&format!("Requested task id {} cannot be found", id));
-            }
-        }
-    })
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum ImageFormat {
+    #[serde(rename = "png")]
+    Png,
+    #[serde(rename = "jpeg")]
+    Jpeg,
+    #[serde(rename = "gif")]
+    Gif,
 }
 
-#[query]
-fn get_all_tasks() -> Vec<ShortTask> {
-    let caller = caller();
-    let mut tasks = Vec::new();
-
-    STATE.with(|s| {
-        let ledger = s.ledger.borrow();
-        if !ledger.contains_key(&caller) {
-            ic_cdk::trap(&format!("{} has not been registered yet.", caller));
-        }
-        let task_map = s.tasks.borrow();
-        for (task_id_ref, task_internal_ref) in task_map.iter() {
-            let task_internal = (*task_internal_ref).clone();
-            tasks.push(ShortTask {
-                id: task_id_ref.clone(),
-                submitter: task_internal.submitter,
-                task_type: task_internal.task_type,
-                status: task_internal.status,
-            })
+impl ImageFormat {
+    fn mime_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
         }
-    });
+    }
+}
 
-    tasks
+#[derive(Clone, Debug, CandidType, Deserialize)]
+enum ImageSource {
+    // The raw bytes of the image, embedded in the task payload.
+    #[serde(rename = "data")]
+    Data(ByteBuf),
+    // A reference to an image hosted elsewhere.
+    #[serde(rename = "url")]
+    Url(String),
 }
 
-#[query]
-fn get_balance() -> Amount {
-    let caller = caller();
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct ImageDimensions {
+    width: u32,
+    height: u32,
+}
 
-    STATE.with(|s| {
-        let ledger = s.ledger.borrow();
-        match ledger.get(&caller) {
-            Some(amount) => *amount,
-            None => {
-                ic_cdk::trap(&format!("{} has not been registered yet.", caller));
-            }
-        }
-    })
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct TranslateTextInput {
+    input: String,
+    language: Language,
 }
 
-#[update]
-fn answer_task(task_id: TaskId, content: Content) -> AnswerId {
-    let caller = caller();
-    STATE.with(|s| {
-        let ledger = s.ledger.borrow();
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct EditImageInput {
+    source: ImageSource,
+    // The MIME type of the source image.
+    format: ImageFormat,
+    instructions: String,
+    // If set, answers must have exactly these dimensions.
+    target_dimensions: Option<ImageDimensions>,
+    // The MIME type answers must be encoded in.
+    target_format: ImageFormat,
+}
 
-        // Precondition: caller is a principal on the ledger
-        if !ledger.contains_key(&caller) {
-            ic_cdk::trap(&format!(
-                "Principal {} cannot provide an answer as this is not a registered\
-             user on the ledger.",
-                caller
-            ));
+impl EditImageInput {
+    fn validate(&self) -> Result<(), String> {
+        if self.instructions.trim().is_empty() {
+            return Err("Instructions must not be empty.".to_string());
         }
-    });
-
-    STATE.with(|s| {
-        let mut tasks = s.tasks.borrow_mut();
-        let mut answers = s.answers.borrow_mut();
 
-        match tasks.get_mut(&task_id) {
-            // the task ID does not exist
-            None => {
-                ic_cdk::trap(&format!(
-                    "Cannot provide an answer to task with ID {} as this task does not exist.",
-                    task_id));
+        match &self.source {
+            ImageSource::Data(bytes) => {
+                image_dimensions(&self.format, bytes)?;
             }
-            Some(task) => {
-                // Precondition: there are less than max_answers for taskID
-                if task.answers.len() >= MAX_NUMBER_ANSWERS {
-                    ic_cdk::trap(&format!(
-                        "Cannot provide an answer to task {} as the maximum number of {} answers is already \
-                    reached.",
-                        task_id, MAX_NUMBER_ANSWERS));
+            ImageSource::Url(url) => {
+                if !url.starts_with("https://") {
+                    return Err(format!("Image URL must use https, but {} was given.", url));
                 }
+            }
+        }
 
-                // Precondition: the deadline for the task has not expired
-                if task.deadline < time() {
-                    ic_cdk::trap(&format!(
-                        "No new solution can be provided as the deadline for the task {} has already expired.",
-                        task_id));
-                }
+        if let Some(dimensions) = &self.target_dimensions {
+            if dimensions.width == 0
+                || dimensions.height == 0
+                || dimensions.width > MAX_IMAGE_DIMENSION
+                || dimensions.height > MAX_IMAGE_DIMENSION
+            {
+                return Err(format!(
+                    "Target dimensions must be between 1 and {} pixels, but {}x{} was given.",
+                    MAX_IMAGE_DIMENSION, dimensions.width, dimensions.height
+                ));
+            }
+        }
 
-                // Precondition: the solution's size is less than MAX_CONTENT_SIZE
-                if content.len() > MAX_CONTENT_SIZE {
-                    ic_cdk::trap(&format!(
-                        "Maximum size of solution is {} but {} was given.",
-                        MAX_CONTENT_SIZE, content.len()
-                    ));
-                }
+        Ok(())
+    }
 
-                // Precondition: the caller hasn’t submitted an answer for this task
-                for answer_id in task.answers.iter() {
-                    match answers.get(answer_id){
-                        Some(answer) => {
-                            if caller == answer.submitter {
-                                ic_cdk::trap(&format!(
-                                    "The principal {} already submitted an answer for the task with ID {}.",
-                                    caller, task_id));
-                            }
-                        },
-                        // this is a case which should not occur, but let's catch it just to be sure
-                        None => {
-                            ic_cdk::trap(&format!(
-                                "The answer with ID {} was listed in task with ID {} even though there is no\
-                        such answer recorded.",
-                                answer_id, task_id));
-                        }
-                    }
-                }
-
-                // Now that all the preconditions are met, make the new answer and submit it
-                let answer_id = s.next_answer_id.replace_with(|&mut old| old + 1);
-                task.answers.insert(answer_id);
-                answers.insert(
-                    answer_id,
-                    Answer {
-                        submitter: caller,
-                        submission_time: time(),
-                        content,
-                        votes: vec![],
-                    },
-                );
-                answer_id
+    // Checks that an answer is a well-formed image in the requested format
+    // and, if requested, of the target dimensions.
+    fn validate_answer(&self, content: &[u8]) -> Result<(), String> {
+        let dimensions = image_dimensions(&self.target_format, content)?;
+        if let Some(target) = &self.target_dimensions {
+            if *target != dimensions {
+                return Err(format!(
+                    "Expected an image of {}x{} pixels, but {}x{} was given.",
+                    target.width, target.height, dimensions.width, dimensions.height
+                ));
             }
         }
-    })
+        Ok(())
+    }
 }
 
-#[update]
-fn vote(answer_id: AnswerId, task_id: TaskId, choice: Choice) {
-    let caller = caller();
-    STATE.with(|s| {
-        let ledger = s.ledger.borrow();
-        let mut answers = s.answers.borrow_mut();
-        let mut tasks = s.tasks.borrow_mut();
+// Parses the header of an image encoded in `format` and returns its
+// dimensions. Fails if the bytes are not a well-formed image of that format.
+fn image_dimensions(format: &ImageFormat, bytes: &[u8]) -> Result<ImageDimensions, String> {
+    let malformed = || format!("Content is not a well-formed {} image.", format.mime_type());
+    let be_u16 = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]) as u32;
+    let be_u32 =
+        |i: usize| u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
 
-        // Precondition: caller is a principal on the ledger
-        if !ledger.contains_key(&caller) {
-            ic_cdk::trap(&format!("Principal {} cannot provide an answer as this is not a registered\
-             user on the ledger.", caller));
+    let dimensions = match format {
+        ImageFormat::Png => {
+            const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+            const IEND: [u8; 12] = [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82];
+            // The signature must be followed by the IHDR chunk and the file
+            // must end with the IEND chunk.
+            if bytes.len() < SIGNATURE.len() + 25 + IEND.len()
+                || bytes[..8] != SIGNATURE
+                || be_u32(8) != 13
+                || &bytes[12..16] != b"IHDR"
+                || bytes[bytes.len() - IEND.len()..] != IEND
+            {
+                return Err(malformed());
+            }
+            ImageDimensions {
+                width: be_u32(16),
+                height: be_u32(20),
+            }
         }
-        match answers.get_mut(&answer_id) {
-            // Precondition: the answerID exists
-            None => {
-                ic_cdk::trap(&format!(
-                    "Principal {} cannot vote on answer with ID {} as this answer does not exist.",
-                    caller, answer_id));
-            },
-            Some(answer) => {
-                match tasks.get_mut(&task_id) {
-                    // Precondition: the taskID exists
-                    None => {
-                        ic_cdk::trap(&format!(
-                            "Principal {} cannot vote on task with ID {} as this task does not exist.",
-                            caller, task_id));
-                    },
-                    Some(task) => {
-                        // Precondition: answerID is an answer for the given task
-                        if !task.answers.contains(&answer_id){
-                            ic_cdk::trap(&format!(
-                                "The answer with ID {} is not an answer for task with ID {}.",
-                                answer_id, task_id
-                            ));
-                        }
-                        // Precondition: the task’s deadline has not been reached
-                        if task.deadline < time() {
-                            ic_cdk::trap(&format!(
-                                "Cannot vote on answer with ID {} as the deadline of the corresponding\
-                                task has expired.",
-                                answer_id
-                            ));
-                        }
-                        // Precondition: the caller has not voted on this answer yet
-                        for existing_vote in answer.votes.iter() {
-                            if existing_vote.voter == caller {
-                                ic_cdk::trap(&format!(
-                                    "Principal {} has already voted on answer {}.",
-                                    caller, answer_id
-                                ));
-                            }
+        ImageFormat::Gif => {
+            // Header, logical screen descriptor and the trailer byte.
+            if bytes.len() < 14
+                || (&bytes[..6] != b"GIF87a" && &bytes[..6] != b"GIF89a")
+                || bytes[bytes.len() - 1] != 0x3b
+            {
+                return Err(malformed());
+            }
+            ImageDimensions {
+                width: u16::from_le_bytes([bytes[6], bytes[7]]) as u32,
+                height: u16::from_le_bytes([bytes[8], bytes[9]]) as u32,
+            }
+        }
+        ImageFormat::Jpeg => {
+            // Must start with SOI and end with EOI.
+            if bytes.len() < 4
+                || bytes[..2] != [0xff, 0xd8]
+                || bytes[bytes.len() - 2..] != [0xff, 0xd9]
+            {
+                return Err(malformed());
+            }
+            // Walk the segments until a start of frame marker is found.
+            let mut pos = 2;
+            loop {
+                if pos + 4 > bytes.len() || bytes[pos] != 0xff {
+                    return Err(malformed());
+                }
+                let marker = bytes[pos + 1];
+                let length = be_u16(pos + 2) as usize;
+                if length < 2 || pos + 2 + length > bytes.len() {
+                    return Err(malformed());
+                }
+                match marker {
+                    // SOF0-SOF15, except DHT (0xc4), JPG (0xc8) and DAC (0xcc).
+                    0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                        if length < 7 {
+                            return Err(malformed());
                         }
-                        // At this point all the preconditions are met and we can update the vote
-                        let vote = Vote {
-                            voter: caller,
-                            choice,
+                        break ImageDimensions {
+                            width: be_u16(pos + 7),
+                            height: be_u16(pos + 5),
                         };
-                        answer.votes.push(vote);
                     }
+                    // Start of scan before any frame header.
+                    0xda => return Err(malformed()),
+                    _ => pos += 2 + length,
                 }
             }
         }
-    });
+    };
+
+    if dimensions.width == 0 || dimensions.height == 0 {
+        return Err(malformed());
+    }
+    Ok(dimensions)
 }
 
-#[pre_upgrade]
-fn save_data() {
-    STATE.with(|s| {
-        if let Err(err) = stable_save((
-            s.next_task_id.take(),
-            s.tasks.take(),
-            s.answers.take(),
-            s.next_answer_id.take(),
-            s.ledger.take(),
-        )) {
-            ic_cdk::trap(&format!("Could not store data to stable memory: {}", err));
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Answer {
+    submitter: Principal,
+    submission_time: Timestamp,
+    content: Content,
+    votes: Vec<Vote>,
+    // The sealed votes that have not been revealed yet.
+    commitments: Vec<Commitment>,
+    // Every vote changed or retracted, oldest first.
+    vote_changes: Vec<VoteChange>,
+    // The contents replaced by edits, oldest first.
+    revisions: Vec<Revision>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Revision {
+    content: Content,
+    replaced_at: Timestamp,
+}
+
+// A changed or retracted vote. Sealed votes are recorded as their hashes.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct VoteChange {
+    voter: Principal,
+    previous: Ballot,
+    // None if the vote was retracted.
+    current: Option<Ballot>,
+    timestamp: Timestamp,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Task {
+    submitter: Principal,
+    task_type: TaskType,
+    payload: TaskPayload,
+    answer_deadline: Timestamp,
+    vote_deadline: Timestamp,
+    reward: Amount,
+    answers: Vec<AnswerWithId>,
+    status: TaskStatus,
+    cancellation_policy: CancellationPolicy,
+    created_at: Timestamp,
+    sponsors: Vec<Sponsor>,
+    payout_scheme: PayoutScheme,
+    payouts: Vec<Payout>,
+    vote_weighting: VoteWeighting,
+    language: Option<Language>,
+    min_skill_score: Option<i64>,
+    commit_reveal: Option<CommitReveal>,
+    answer_visibility: AnswerVisibility,
+    settlement_mode: SettlementMode,
+    dispute_deadline: Option<Timestamp>,
+    payouts_released: bool,
+    dispute: Option<Dispute>,
+    voter_pool: Option<VoterPool>,
+    voter_rewards: Vec<VoterReward>,
+    quorum: Option<Quorum>,
+    review_extended: bool,
+    close_reason: Option<CloseReason>,
+    conflict_of_interest: ConflictOfInterest,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct ShortTask {
+    id: TaskId,
+    submitter: Principal,
+    task_type: TaskType,
+    status: TaskStatus,
+    reward: Amount,
+    answer_deadline: Timestamp,
+    vote_deadline: Timestamp,
+    answer_count: u64,
+    // Includes sealed votes that have not been revealed yet.
+    total_votes: u64,
+    language: Option<Language>,
+    // The start of the text to translate or of the editing instructions.
+    payload_preview: String,
+}
+
+// Selects the tasks returned by `list_tasks`. Unset fields match any task.
+// The deadline range applies to the answer deadline and is inclusive.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+struct TaskFilter {
+    status: Option<TaskStatus>,
+    task_type: Option<TaskType>,
+    submitter: Option<Principal>,
+    language: Option<Language>,
+    min_reward: Option<Amount>,
+    deadline_after: Option<Timestamp>,
+    deadline_before: Option<Timestamp>,
+}
+
+impl TaskFilter {
+    fn matches(&self, task: &TaskInternal) -> bool {
+        if let Some(status) = &self.status {
+            if *status != task.status {
+                return false;
+            }
+        }
+        if let Some(task_type) = &self.task_type {
+            if *task_type != task.task_type {
+                return false;
+            }
+        }
+        if let Some(submitter) = &self.submitter {
+            if *submitter != task.submitter {
+                return false;
+            }
+        }
+        if self.language.is_some() && self.language != task.language {
+            return false;
+        }
+        if let Some(min_reward) = self.min_reward {
+            if task.reward < min_reward {
+                return false;
+            }
+        }
+        if let Some(deadline_after) = self.deadline_after {
+            if task.answer_deadline < deadline_after {
+                return false;
+            }
+        }
+        if let Some(deadline_before) = self.deadline_before {
+            if task.answer_deadline > deadline_before {
+                return false;
+            }
         }
+        true
+    }
+}
+
+// An answer written by the caller, with what it earned once the task's
+// payouts were released.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct MyAnswer {
+    answer_id: AnswerId,
+    task_id: TaskId,
+    task_status: TaskStatus,
+    submission_time: Timestamp,
+    earned: Amount,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct AnswerPage {
+    answers: Vec<MyAnswer>,
+    next_cursor: Option<AnswerId>,
+}
+
+// A vote cast by the caller, with its share of the task's voter pool.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct MyVote {
+    answer_id: AnswerId,
+    task_id: TaskId,
+    task_status: TaskStatus,
+    ballot: Ballot,
+    stake: Amount,
+    earned: Amount,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct VotePage {
+    votes: Vec<MyVote>,
+    next_cursor: Option<AnswerId>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct TaskPage {
+    tasks: Vec<ShortTask>,
+    // The cursor for the next page, if there are more matching tasks.
+    next_cursor: Option<TaskId>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Vote {
+    voter: Principal,
+    choice: Choice,
+    weight: u64,
+    // The tokens locked by the voter, released with the task's payouts.
+    stake: Amount,
+}
+
+// A vote as cast: in the clear or, on tasks with commit-reveal voting,
+// sealed as the hash computed by `commitment_hash`.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum Ballot {
+    #[serde(rename = "choice")]
+    Choice(Choice),
+    #[serde(rename = "commitment")]
+    Commitment(ByteBuf),
+}
+
+// A sealed vote. The weight and stake are fixed when the vote is cast.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Commitment {
+    voter: Principal,
+    hash: ByteBuf,
+    weight: u64,
+    stake: Amount,
+    deposit: Amount,
+}
+
+// The hash sealing a vote: the SHA-256 of the voter's principal, one byte
+// for the choice (1 for yes, 0 for no) and the salt.
+fn commitment_hash(voter: &Principal, choice: &Choice, salt: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(voter.as_slice());
+    hasher.update(match choice {
+        Choice::Yes => [1],
+        Choice::No => [0],
     });
+    hasher.update(salt);
+    hasher.finalize().to_vec()
 }
 
-#[post_upgrade]
-fn retrieve_data() {
-    match stable_restore::<(
-        TaskId,
-        HashMap<TaskId, TaskInternal>,
-        HashMap<AnswerId, Answer>,
-        AnswerId,
-        HashMap<Principal, Amount>,
-    )>() {
-        Ok((next_task_id, tasks, answers, next_answer_id, ledger)) => {
-            STATE.with(|s| {
-                s.next_task_id.replace(next_task_id);
-                s.tasks.replace(tasks);
-                s.answers.replace(answers);
-                s.next_answer_id.replace(next_answer_id);
-                s.ledger.replace(ledger);
-            });
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct TaskInternal {
+    submitter: Principal,
+    task_type: TaskType,
+    payload: TaskPayload,
+    // Answers are accepted until the answer deadline and votes from then on
+    // until the vote deadline.
+    answer_deadline: Timestamp,
+    vote_deadline: Timestamp,
+    reward: Amount,
+    answers: HashSet<AnswerId>,
+    status: TaskStatus,
+    cancellation_policy: CancellationPolicy,
+    created_at: Timestamp,
+    // The contributions to the reward, starting with the submitter's.
+    sponsors: Vec<Sponsor>,
+    payout_scheme: PayoutScheme,
+    // The rewards paid out when the task was settled.
+    payouts: Vec<Payout>,
+    vote_weighting: VoteWeighting,
+    // The target language of translation tasks.
+    language: Option<Language>,
+    min_skill_score: Option<i64>,
+    commit_reveal: Option<CommitReveal>,
+    answer_visibility: AnswerVisibility,
+    settlement_mode: SettlementMode,
+    // The payouts of tasks settled by votes are held in escrow until the
+    // dispute deadline, during which the outcome can be disputed.
+    dispute_deadline: Option<Timestamp>,
+    payouts_released: bool,
+    dispute: Option<Dispute>,
+    voter_pool: Option<VoterPool>,
+    voter_rewards: Vec<VoterReward>,
+    quorum: Option<Quorum>,
+    review_extended: bool,
+    close_reason: Option<CloseReason>,
+    conflict_of_interest: ConflictOfInterest,
+}
+
+impl TaskInternal {
+    // The skills exercised by answering the task.
+    fn skills(&self) -> Vec<Skill> {
+        let mut skills = vec![Skill::TaskType(self.task_type.clone())];
+        if let Some(language) = &self.language {
+            skills.push(Skill::Language(language.clone()));
         }
-        Err(err) => {
-            ic_cdk::trap(&format!("Could not read data from stable memory: {}", err));
+        skills
+    }
+
+    // The time after which the task is settled: the vote deadline, followed
+    // by the reveal period with commit-reveal voting.
+    fn settlement_time(&self) -> Timestamp {
+        let reveal_period = self.commit_reveal.as_ref().map_or(0, |cr| cr.reveal_period);
+        self.vote_deadline.saturating_add(reveal_period)
+    }
+
+    // The time until which the submitter can accept an answer, if the task
+    // is in acceptance mode.
+    fn acceptance_deadline(&self) -> Option<Timestamp> {
+        match self.settlement_mode {
+            SettlementMode::Vote => None,
+            SettlementMode::SubmitterAccepts { grace_period } => {
+                Some(self.answer_deadline.saturating_add(grace_period))
+            }
         }
     }
-}
 
-#[export_name = "canister_heartbeat"]
-fn hearbeat() {
-    STATE.with(|s| {
-        for (task_id, task) in s.tasks.borrow_mut().iter_mut().filter(|(_, t)| t.status == TaskStatus::Open) {
-            if task.deadline < time() {
-                task.status = TaskStatus::Closed;
-                let mut votes_exist = false;
-                let mut max_vote_diff = std::i64::MIN;
-                let mut submitter_for_top_voted_answer = Principal::anonymous();
-                let all_answers = s.answers.borrow();
-                let mut task_answers = vec![];
-                for answer_id in task.answers.iter() {
-                    match all_answers.get(answer_id) {
-                        // This is a case which should not occur, but let's catch it just to be sure
-                        None => { ic_cdk::trap(&format!(
-                            "The answer with ID {} was listed in task with ID {} even though there is no\
-                            such answer recorded.", answer_id, task_id)
-                        );}
-                        Some(answer) => {
-                            task_answers.push(answer);
-                        }
-                    }
-                }
+    // The time at which the heartbeat moves the task into its next phase.
+    fn next_phase_change(&self) -> Timestamp {
+        match (&self.status, &self.dispute_deadline, &self.dispute) {
+            (TaskStatus::Open, _, _) => self.answer_deadline,
+            (TaskStatus::Closed, Some(dispute_deadline), _) => *dispute_deadline,
+            (TaskStatus::Disputed, _, Some(dispute)) => dispute.deadline,
+            _ => self.settlement_time(),
+        }
+    }
+
+    // Whether the heartbeat still has to move the task into another phase.
+    fn is_pending(&self) -> bool {
+        match self.status {
+            TaskStatus::Open | TaskStatus::InReview | TaskStatus::Disputed => true,
+            TaskStatus::Closed => !self.payouts_released,
+            TaskStatus::Cancelled => false,
+        }
+    }
 
-                task_answers.sort_by(|a, b| a.submission_time.cmp(&b.submission_time));
+    // The answer whose voters share the voter pool: the top-ranked paid
+    // answer, if any.
+    fn winning_answer(&self) -> Option<AnswerId> {
+        self.payouts.first().map(|payout| payout.answer_id)
+    }
 
-                for answer in task_answers.iter() {
-                    let num_votes = answer.votes.len();
-                    if num_votes > 0 {
-                        votes_exist = true;
-                        let vote_diff = answer.votes
-                            .iter()
-                            .map(|v| match v.choice { Choice::Yes => 1, Choice::No => -1})
-                            .sum::<i64>();
-                        if vote_diff > max_vote_diff {
-                            max_vote_diff = vote_diff;
-                            submitter_for_top_voted_answer = answer.submitter;
-                        }
+    // The part of a vote's stake that is forfeited to the voter pool.
+    fn slashed_stake(&self, answer_id: AnswerId, vote: &Vote) -> Amount {
+        match &self.voter_pool {
+            Some(voter_pool)
+                if vote.choice == Choice::No && self.winning_answer() == Some(answer_id) =>
+            {
+                (vote.stake as u128 * voter_pool.slash_percent as u128 / 100) as Amount
+            }
+            _ => 0,
+        }
+    }
+
+    // Summarizes the task for listings.
+    fn summary(&self, id: TaskId, answers: &HashMap<AnswerId, Answer>) -> ShortTask {
+        let task_answers: Vec<&Answer> = self
+            .answers
+            .iter()
+            .filter_map(|answer_id| answers.get(answer_id))
+            .collect();
+        // Sealed votes that were not revealed by settlement no longer count.
+        let in_review = self.status == TaskStatus::InReview;
+        let total_votes = task_answers
+            .iter()
+            .map(|answer| {
+                answer.votes.len()
+                    + if in_review {
+                        answer.commitments.len()
+                    } else {
+                        0
                     }
-                }
+            })
+            .sum::<usize>();
+        // The payload was validated in `submit_task`, but a listing should
+        // not fail because of a single task.
+        let text = match self.task_type {
+            TaskType::TranslateText => Decode!(&self.payload, TranslateTextInput)
+                .map(|input| input.input)
+                .unwrap_or_default(),
+            TaskType::EditImage => Decode!(&self.payload, EditImageInput)
+                .map(|input| input.instructions)
+                .unwrap_or_default(),
+        };
+        ShortTask {
+            id,
+            submitter: self.submitter,
+            task_type: self.task_type.clone(),
+            status: self.status.clone(),
+            reward: self.reward,
+            answer_deadline: self.answer_deadline,
+            vote_deadline: self.vote_deadline,
+            answer_count: task_answers.len() as u64,
+            total_votes: total_votes as u64,
+            language: self.language.clone(),
+            payload_preview: text.chars().take(PAYLOAD_PREVIEW_LENGTH).collect(),
+        }
+    }
+
+    // The principals who took part in the task, who cannot be jurors in its
+    // disputes.
+    fn participants(&self, answers: &HashMap<AnswerId, Answer>) -> HashSet<Principal> {
+        let mut participants: HashSet<Principal> = self
+            .sponsors
+            .iter()
+            .map(|sponsor| sponsor.sponsor)
+            .collect();
+        participants.insert(self.submitter);
+        for answer in self
+            .answers
+            .iter()
+            .filter_map(|answer_id| answers.get(answer_id))
+        {
+            participants.insert(answer.submitter);
+            participants.extend(answer.votes.iter().map(|vote| vote.voter));
+            participants.extend(answer.commitments.iter().map(|commitment| commitment.voter));
+        }
+        participants
+    }
+
+    // Whether `principal` can see the content of an answer to the task.
+    fn shows_content(&self, principal: &Principal, answer: &Answer, now: Timestamp) -> bool {
+        match self.answer_visibility {
+            AnswerVisibility::Public => true,
+            AnswerVisibility::Hidden => {
+                self.answer_deadline < now
+                    || *principal == self.submitter
+                    || *principal == answer.submitter
+            }
+        }
+    }
+
+    // The skill checked against `min_skill_score`.
+    fn required_skill(&self) -> Skill {
+        match &self.language {
+            Some(language) => Skill::Language(language.clone()),
+            None => Skill::TaskType(self.task_type.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Sponsor {
+    sponsor: Principal,
+    amount: Amount,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Payout {
+    answer_id: AnswerId,
+    recipient: Principal,
+    amount: Amount,
+}
+
+// An answer that received votes, with the sum of its weighted votes.
+#[derive(Clone, Debug, PartialEq)]
+struct RankedAnswer {
+    answer_id: AnswerId,
+    submitter: Principal,
+    net_votes: i128,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct AnswerWithId {
+    answer_id: AnswerId,
+    answer: Answer,
+    // Whether the content was left out because answers to the task are
+    // still hidden from the caller.
+    content_hidden: bool,
+}
+
+// An ICRC-1 account. A principal's default account, which is the one used
+// by the task workflow, has no subaccount.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, CandidType, Deserialize)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Subaccount>,
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Account {
+            owner,
+            subaccount: None,
+        }
+    }
+}
+
+impl Account {
+    // The all-zero subaccount is the default account.
+    fn normalized(self) -> Self {
+        match &self.subaccount {
+            Some(subaccount) if subaccount.iter().all(|b| *b == 0) => Account::from(self.owner),
+            _ => self,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ButlerError> {
+        match &self.subaccount {
+            Some(subaccount) if subaccount.len() != SUBACCOUNT_SIZE => {
+                Err(ButlerError::InvalidSubaccount)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// The canister-owned account that holds the reward of a task until it is paid out.
+fn escrow_account(task_id: TaskId) -> Account {
+    let mut subaccount = vec![0; SUBACCOUNT_SIZE];
+    subaccount[0] = ESCROW_SUBACCOUNT_TAG;
+    subaccount[SUBACCOUNT_SIZE - 8..].copy_from_slice(&task_id.to_be_bytes());
+    Account {
+        owner: canister_id(),
+        subaccount: Some(ByteBuf::from(subaccount)),
+    }
+}
+
+// The ID of this canister. Outside of a canister, i.e. in unit tests, the
+// management canister stands in for it so that escrows can be exercised.
+#[cfg(target_arch = "wasm32")]
+fn canister_id() -> Principal {
+    ic_cdk::id()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn canister_id() -> Principal {
+    Principal::management_canister()
+}
+
+// Sets the balance of an account. Subaccounts are dropped from the ledger
+// once they are empty, while default accounts mark the principals on the
+// ledger and are always kept.
+fn set_balance(ledger: &mut HashMap<Account, Amount>, account: &Account, balance: Amount) {
+    if balance == 0 && account.subaccount.is_some() {
+        ledger.remove(account);
+    } else {
+        ledger.insert(account.clone(), balance);
+    }
+}
+
+// Applies a transaction to the ledger and appends it to the transaction
+// log. Transactions without a source mint tokens and transactions without a
+// destination burn them. The ledger is left untouched if the transaction
+// cannot be applied.
+fn execute(
+    ledger: &mut HashMap<Account, Amount>,
+    transactions: &mut TransactionLog,
+    mut transaction: Transaction,
+) -> Result<BlockIndex, ButlerError> {
+    let amount = transaction.amount;
+    if let Some(from) = &transaction.from {
+        let balance = ledger.get(from).copied().unwrap_or(0);
+        if balance < amount {
+            return Err(ButlerError::InsufficientBalance {
+                balance,
+                required: amount,
+            });
+        }
+    }
+    if transaction.from != transaction.to {
+        if let Some(to) = &transaction.to {
+            let balance = ledger.get(to).copied().unwrap_or(0);
+            if balance.checked_add(amount).is_none() {
+                return Err(ButlerError::BalanceOverflow(to.clone()));
+            }
+        }
+        if let Some(from) = &transaction.from {
+            let balance = ledger.get(from).copied().unwrap_or(0);
+            set_balance(ledger, from, balance - amount);
+        }
+        if let Some(to) = &transaction.to {
+            let balance = ledger.get(to).copied().unwrap_or(0);
+            set_balance(ledger, to, balance + amount);
+        }
+    }
+
+    let index = transactions.len() as BlockIndex;
+    transaction.index = index;
+    transactions.push(transaction);
+    Ok(index)
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum TransactionKind {
+    // Tokens granted to a newly registered principal.
+    #[serde(rename = "registration_grant")]
+    RegistrationGrant,
+    // The burned cost of submitting a task.
+    #[serde(rename = "submission_fee")]
+    SubmissionFee,
+    // A reward moved into a task's escrow account.
+    #[serde(rename = "escrow")]
+    Escrow,
+    // A reward paid out of escrow for an answer.
+    #[serde(rename = "payout")]
+    Payout,
+    // A reward returned out of escrow to the task submitter.
+    #[serde(rename = "refund")]
+    Refund,
+    // Tokens locked in a task's escrow by a voter.
+    #[serde(rename = "stake")]
+    Stake,
+    // Locked tokens returned to a voter.
+    #[serde(rename = "stake_release")]
+    StakeRelease,
+    // Tokens locked in a task's escrow when opening a dispute.
+    #[serde(rename = "dispute_bond")]
+    DisputeBond,
+    // A dispute bond returned because the dispute succeeded or no juror
+    // delivered a verdict.
+    #[serde(rename = "bond_refund")]
+    BondRefund,
+    // A share of a failed dispute's bond paid to a juror.
+    #[serde(rename = "juror_reward")]
+    JurorReward,
+    // A share of a task's voter pool paid to a voter.
+    #[serde(rename = "voter_reward")]
+    VoterReward,
+    // The burned deposit of a sealed vote that was never revealed.
+    #[serde(rename = "penalty")]
+    Penalty,
+    #[serde(rename = "transfer")]
+    Transfer,
+}
+
+// An entry of the append-only transaction log. Its index in
+// `State::transactions` is the block index returned to the caller.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct Transaction {
+    index: BlockIndex,
+    kind: TransactionKind,
+    from: Option<Account>,
+    to: Option<Account>,
+    amount: Amount,
+    task_id: Option<TaskId>,
+    memo: Option<Memo>,
+    created_at_time: Option<Timestamp>,
+    timestamp: Timestamp,
+}
+
+impl Transaction {
+    // The index is assigned when the transaction is executed.
+    fn new(
+        kind: TransactionKind,
+        from: Option<Account>,
+        to: Option<Account>,
+        amount: Amount,
+        timestamp: Timestamp,
+    ) -> Self {
+        Transaction {
+            index: 0,
+            kind,
+            from,
+            to,
+            amount,
+            task_id: None,
+            memo: None,
+            created_at_time: None,
+            timestamp,
+        }
+    }
+
+    // The owners of the accounts the transaction moves tokens from or to.
+    fn principals(&self) -> Vec<Principal> {
+        let mut principals: Vec<Principal> = self
+            .from
+            .iter()
+            .chain(self.to.iter())
+            .map(|account| account.owner)
+            .collect();
+        principals.dedup();
+        principals
+    }
+}
+
+// The log of all transactions, which can be read like a `Vec`. New
+// transactions are appended with `push`, which also keeps the index of the
+// transactions of every principal. The index is derived from the log and
+// not persisted across upgrades.
+#[derive(Default)]
+struct TransactionLog {
+    transactions: Vec<Transaction>,
+    by_principal: HashMap<Principal, Vec<BlockIndex>>,
+}
+
+impl TransactionLog {
+    fn new(transactions: Vec<Transaction>) -> Self {
+        let mut log = TransactionLog::default();
+        for transaction in transactions {
+            log.push(transaction);
+        }
+        log
+    }
+
+    fn push(&mut self, transaction: Transaction) {
+        for principal in transaction.principals() {
+            self.by_principal
+                .entry(principal)
+                .or_default()
+                .push(transaction.index);
+        }
+        self.transactions.push(transaction);
+    }
+
+    fn pop(&mut self) -> Option<Transaction> {
+        let transaction = self.transactions.pop()?;
+        for principal in transaction.principals() {
+            if let Some(indices) = self.by_principal.get_mut(&principal) {
+                indices.pop();
+                if indices.is_empty() {
+                    self.by_principal.remove(&principal);
+                }
+            }
+        }
+        Some(transaction)
+    }
+
+    // The indices of the transactions that move tokens from or to any
+    // account of `principal`, oldest first.
+    fn history(&self, principal: &Principal) -> &[BlockIndex] {
+        self.by_principal
+            .get(principal)
+            .map_or(&[], |indices| &indices[..])
+    }
+
+    fn into_inner(self) -> Vec<Transaction> {
+        self.transactions
+    }
+}
+
+impl std::ops::Deref for TransactionLog {
+    type Target = Vec<Transaction>;
+
+    fn deref(&self) -> &Vec<Transaction> {
+        &self.transactions
+    }
+}
+
+// Pays `amount` out of the escrow account of a task. Failing to do so means
+// that the escrow is out of sync with the task.
+fn pay_from_escrow(
+    ledger: &mut HashMap<Account, Amount>,
+    transactions: &mut TransactionLog,
+    task_id: TaskId,
+    kind: TransactionKind,
+    recipient: Principal,
+    amount: Amount,
+    now: Timestamp,
+) -> Result<(), String> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let mut transaction = Transaction::new(
+        kind,
+        Some(escrow_account(task_id)),
+        Some(Account::from(recipient)),
+        amount,
+        now,
+    );
+    transaction.task_id = Some(task_id);
+    match execute(ledger, transactions, transaction) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!(
+            "Could not pay {} out of the escrow of task {}: {:?}",
+            amount, task_id, err
+        )),
+    }
+}
+
+// Burns `amount` out of the escrow account of a task. Like
+// `pay_from_escrow`, this fails if the escrow is out of sync with the task.
+fn burn_from_escrow(
+    ledger: &mut HashMap<Account, Amount>,
+    transactions: &mut TransactionLog,
+    task_id: TaskId,
+    kind: TransactionKind,
+    amount: Amount,
+    now: Timestamp,
+) -> Result<(), String> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let mut transaction = Transaction::new(kind, Some(escrow_account(task_id)), None, amount, now);
+    transaction.task_id = Some(task_id);
+    match execute(ledger, transactions, transaction) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!(
+            "Could not burn {} out of the escrow of task {}: {:?}",
+            amount, task_id, err
+        )),
+    }
+}
+
+// Traps if moving tokens out of an escrow failed. Update calls rely on the
+// trap to roll back the changes they made before.
+fn trap_on_error(result: Result<(), String>) {
+    if let Err(err) = result {
+        ic_cdk::trap(&err);
+    }
+}
+
+// Undoes the transactions appended to the log after its first `len`
+// entries, latest first, and removes them from the log.
+fn revert_transactions(
+    ledger: &mut HashMap<Account, Amount>,
+    transactions: &mut TransactionLog,
+    len: usize,
+) {
+    while transactions.len() > len {
+        // Safe because the log is longer than `len`.
+        let transaction = transactions.pop().unwrap();
+        if transaction.from == transaction.to {
+            continue;
+        }
+        if let Some(to) = &transaction.to {
+            let balance = ledger.get(to).copied().unwrap_or(0);
+            set_balance(ledger, to, balance - transaction.amount);
+        }
+        if let Some(from) = &transaction.from {
+            let balance = ledger.get(from).copied().unwrap_or(0);
+            set_balance(ledger, from, balance + transaction.amount);
+        }
+    }
+}
+
+// Returns the stakes locked by the voters of a task. The deposits of sealed
+// votes that were never revealed are burned if `forfeit_deposits` is set and
+// returned otherwise. Slashed stakes stay in escrow for the voter pool.
+// Failing to find an answer of the task means the state is inconsistent.
+fn release_stakes(
+    ledger: &mut HashMap<Account, Amount>,
+    transactions: &mut TransactionLog,
+    answers: &HashMap<AnswerId, Answer>,
+    task_id: TaskId,
+    task: &TaskInternal,
+    forfeit_deposits: bool,
+    now: Timestamp,
+) -> Result<(), String> {
+    let mut answer_ids: Vec<&AnswerId> = task.answers.iter().collect();
+    answer_ids.sort();
+    for answer_id in answer_ids {
+        let answer = answers
+            .get(answer_id)
+            .ok_or_else(|| format!("Inconsistent state. AnswerId {} cannot be found", answer_id))?;
+        for vote in answer.votes.iter() {
+            pay_from_escrow(
+                ledger,
+                transactions,
+                task_id,
+                TransactionKind::StakeRelease,
+                vote.voter,
+                vote.stake - task.slashed_stake(*answer_id, vote),
+                now,
+            )?;
+        }
+        for commitment in answer.commitments.iter() {
+            if forfeit_deposits {
+                pay_from_escrow(
+                    ledger,
+                    transactions,
+                    task_id,
+                    TransactionKind::StakeRelease,
+                    commitment.voter,
+                    commitment.stake,
+                    now,
+                )?;
+                burn_from_escrow(
+                    ledger,
+                    transactions,
+                    task_id,
+                    TransactionKind::Penalty,
+                    commitment.deposit,
+                    now,
+                )?;
+            } else {
+                pay_from_escrow(
+                    ledger,
+                    transactions,
+                    task_id,
+                    TransactionKind::StakeRelease,
+                    commitment.voter,
+                    commitment.stake + commitment.deposit,
+                    now,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Splits `amount` among the sponsors of a task in proportion to their
+// contributions. The rounding remainder goes to the first sponsor, i.e.
+// the submitter.
+fn pro_rata(sponsors: &[Sponsor], amount: Amount) -> Vec<(Principal, Amount)> {
+    let total: u128 = sponsors.iter().map(|s| s.amount as u128).sum();
+    if total == 0 {
+        return match sponsors.first() {
+            Some(first) => vec![(first.sponsor, amount)],
+            None => vec![],
+        };
+    }
+    let mut shares: Vec<(Principal, Amount)> = sponsors
+        .iter()
+        .map(|s| {
+            (
+                s.sponsor,
+                (amount as u128 * s.amount as u128 / total) as Amount,
+            )
+        })
+        .collect();
+    let distributed: Amount = shares.iter().map(|(_, share)| share).sum();
+    shares[0].1 += amount - distributed;
+    shares
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct TransactionRange {
+    // The total number of transactions that can be paged through.
+    total: u64,
+    transactions: Vec<Transaction>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Subaccount>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Memo>,
+    created_at_time: Option<Timestamp>,
+}
+
+// The error type of `icrc1_transfer`, as defined by the ICRC-1 standard.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: Timestamp },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+impl From<ButlerError> for TransferError {
+    fn from(err: ButlerError) -> Self {
+        match err {
+            ButlerError::InsufficientBalance { balance, .. } => TransferError::InsufficientFunds {
+                balance: Nat::from(balance),
+            },
+            err => TransferError::GenericError {
+                error_code: Nat::from(0u64),
+                message: format!("{:?}", err),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+enum MetadataValue {
+    Nat(Nat),
+    Int(Int),
+    Text(String),
+    Blob(ByteBuf),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct StandardRecord {
+    name: String,
+    url: String,
+}
+
+// The errors returned by the canister's endpoints.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+enum ButlerError {
+    #[serde(rename = "not_registered")]
+    NotRegistered,
+    #[serde(rename = "already_registered")]
+    AlreadyRegistered,
+    #[serde(rename = "task_not_found")]
+    TaskNotFound(TaskId),
+    #[serde(rename = "answer_not_found")]
+    AnswerNotFound(AnswerId),
+    #[serde(rename = "answer_not_in_task")]
+    AnswerNotInTask {
+        answer_id: AnswerId,
+        task_id: TaskId,
+    },
+    #[serde(rename = "deadline_passed")]
+    DeadlinePassed { deadline: Timestamp },
+    #[serde(rename = "insufficient_balance")]
+    InsufficientBalance { balance: Amount, required: Amount },
+    #[serde(rename = "payload_too_large")]
+    PayloadTooLarge { max_size: u64, size: u64 },
+    #[serde(rename = "invalid_payload")]
+    InvalidPayload(String),
+    #[serde(rename = "invalid_duration")]
+    InvalidDuration { min: Duration, max: Duration },
+    #[serde(rename = "too_many_answers")]
+    TooManyAnswers { max: u64 },
+    #[serde(rename = "invalid_answer")]
+    InvalidAnswer(String),
+    // The caller has already answered the task with the given answer.
+    #[serde(rename = "duplicate_answer")]
+    DuplicateAnswer(AnswerId),
+    #[serde(rename = "already_voted")]
+    AlreadyVoted(AnswerId),
+    // Only the author of an answer can edit or withdraw it.
+    #[serde(rename = "not_answer_author")]
+    NotAnswerAuthor(AnswerId),
+    // The caller has not voted on the given answer.
+    #[serde(rename = "vote_not_found")]
+    VoteNotFound(AnswerId),
+    // Answer authors cannot vote on their own answers.
+    #[serde(rename = "own_answer")]
+    OwnAnswer(AnswerId),
+    // The task bars its answer authors from voting.
+    #[serde(rename = "answerer_cannot_vote")]
+    AnswererCannotVote(TaskId),
+    // The task bars its submitter from voting.
+    #[serde(rename = "submitter_cannot_vote")]
+    SubmitterCannotVote(TaskId),
+    // The accounts of the canister itself hold task escrows and cannot
+    // receive transfers.
+    #[serde(rename = "reserved_account")]
+    ReservedAccount(Account),
+    // The operation would overflow the balance of the given account.
+    #[serde(rename = "balance_overflow")]
+    BalanceOverflow(Account),
+    #[serde(rename = "invalid_subaccount")]
+    InvalidSubaccount,
+    #[serde(rename = "invalid_options")]
+    InvalidOptions(String),
+    // The caller is not the submitter of the given task.
+    #[serde(rename = "not_submitter")]
+    NotSubmitter(TaskId),
+    #[serde(rename = "task_not_open")]
+    TaskNotOpen(TaskId),
+    // The task has answers and its cancellation policy does not allow
+    // cancelling it anymore.
+    #[serde(rename = "task_has_answers")]
+    TaskHasAnswers(TaskId),
+    // Tasks cannot stay open longer than `MAX_DURATION` after their creation.
+    #[serde(rename = "extension_too_long")]
+    ExtensionTooLong { max_deadline: Timestamp },
+    #[serde(rename = "invalid_amount")]
+    InvalidAmount,
+    // The caller's skill score is below the minimum set by the submitter.
+    #[serde(rename = "insufficient_skill")]
+    InsufficientSkill { required: i64, actual: i64 },
+    // The vote is sealed on a task with open voting, in the clear on a task
+    // with commit-reveal voting, or its hash has the wrong size.
+    #[serde(rename = "invalid_ballot")]
+    InvalidBallot(String),
+    // The caller has no sealed vote on the given answer.
+    #[serde(rename = "commitment_not_found")]
+    CommitmentNotFound(AnswerId),
+    // The revealed vote does not match the caller's sealed vote on the given
+    // answer.
+    #[serde(rename = "commitment_mismatch")]
+    CommitmentMismatch(AnswerId),
+    #[serde(rename = "reveal_not_open")]
+    RevealNotOpen { opens_at: Timestamp },
+    // Votes are only accepted after the answer deadline.
+    #[serde(rename = "voting_not_open")]
+    VotingNotOpen { opens_at: Timestamp },
+    // The given task is settled by votes only.
+    #[serde(rename = "acceptance_not_enabled")]
+    AcceptanceNotEnabled(TaskId),
+    // Only the submitter and the answerers of a task can dispute it.
+    #[serde(rename = "not_participant")]
+    NotParticipant(TaskId),
+    // The task was not settled by votes, was already disputed or its
+    // payouts were already released.
+    #[serde(rename = "not_disputable")]
+    NotDisputable(TaskId),
+    #[serde(rename = "no_jurors_available")]
+    NoJurorsAvailable,
+    #[serde(rename = "randomness_unavailable")]
+    RandomnessUnavailable(String),
+    #[serde(rename = "no_open_dispute")]
+    NoOpenDispute(TaskId),
+    #[serde(rename = "not_juror")]
+    NotJuror(TaskId),
+    #[serde(rename = "verdict_already_cast")]
+    VerdictAlreadyCast(TaskId),
+}
+
+#[update]
+fn register() -> Result<(), ButlerError> {
+    let caller = caller();
+    register_impl(caller, time())
+}
+
+// Principals that were sent tokens before registering still receive the
+// grant on top of them.
+fn register_impl(caller: Principal, now: Timestamp) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let mut registered = s.registered.borrow_mut();
+        if registered.contains(&caller) {
+            return Err(ButlerError::AlreadyRegistered);
+        }
+        execute(
+            &mut s.ledger.borrow_mut(),
+            &mut s.transactions.borrow_mut(),
+            Transaction::new(
+                TransactionKind::RegistrationGrant,
+                None,
+                Some(Account::from(caller)),
+                INITIAL_TOKENS,
+                now,
+            ),
+        )?;
+        registered.insert(caller);
+        Ok(())
+    })
+}
+
+#[update]
+fn submit_task(
+    task_type: TaskType,
+    payload: TaskPayload,
+    duration: Duration,
+    reward: Amount,
+    options: Option<TaskOptions>,
+) -> Result<TaskId, ButlerError> {
+    let caller = caller();
+    let options = options.unwrap_or_default();
+
+    STATE.with(|s| {
+        let ledger = s.ledger.borrow();
+        match ledger.get(&Account::from(caller)) {
+            Some(balance) => {
+                if *balance < SUBMISSION_COST {
+                    return Err(ButlerError::InsufficientBalance {
+                        balance: *balance,
+                        required: SUBMISSION_COST,
+                    });
+                }
+                Ok(())
+            }
+            None => Err(ButlerError::NotRegistered),
+        }
+    })?;
+
+    let max_payload = match task_type {
+        TaskType::TranslateText => MAX_TASK_PAYLOAD,
+        TaskType::EditImage => MAX_TASK_PAYLOAD + MAX_IMAGE_SIZE,
+    };
+    if payload.len() > max_payload {
+        return Err(ButlerError::PayloadTooLarge {
+            max_size: max_payload as u64,
+            size: payload.len() as u64,
+        });
+    }
+
+    let language = match task_type {
+        TaskType::TranslateText => {
+            let input = Decode!(&payload, TranslateTextInput).map_err(|err| {
+                ButlerError::InvalidPayload(format!("Invalid input for tranlating text: {}", err))
+            })?;
+            Some(input.language)
+        }
+        TaskType::EditImage => {
+            let input = Decode!(&payload, EditImageInput).map_err(|err| {
+                ButlerError::InvalidPayload(format!("Invalid input for editing an image: {}", err))
+            })?;
+            input.validate().map_err(|err| {
+                ButlerError::InvalidPayload(format!("Invalid input for editing an image: {}", err))
+            })?;
+            None
+        }
+    };
+
+    let cancellation_policy = options
+        .cancellation_policy
+        .unwrap_or(CancellationPolicy::WithoutAnswers);
+    if let CancellationPolicy::CompensateAnswerers { percent } = cancellation_policy {
+        if percent > 100 {
+            return Err(ButlerError::InvalidOptions(format!(
+                "Compensation must be at most 100 percent of the reward, but {} was given.",
+                percent
+            )));
+        }
+    }
+
+    let payout_scheme = options
+        .payout_scheme
+        .unwrap_or(PayoutScheme::WinnerTakesAll);
+    payout_scheme.validate()?;
+    let vote_weighting = options.vote_weighting.unwrap_or(VoteWeighting::Equal);
+    let min_skill_score = options.min_skill_score;
+    let commit_reveal = options.commit_reveal;
+    if let Some(commit_reveal) = &commit_reveal {
+        commit_reveal.validate()?;
+    }
+    let answer_visibility = options
+        .answer_visibility
+        .unwrap_or(AnswerVisibility::Public);
+    let review_period = options.review_period.unwrap_or(duration);
+    validate_period("review period", review_period)?;
+    let settlement_mode = options.settlement_mode.unwrap_or(SettlementMode::Vote);
+    settlement_mode.validate(review_period)?;
+    let voter_pool = options.voter_pool;
+    if let Some(voter_pool) = &voter_pool {
+        voter_pool.validate(&vote_weighting)?;
+    }
+    let pool_amount = voter_pool
+        .as_ref()
+        .map_or(0, |voter_pool| voter_pool.amount);
+    let quorum = options.quorum;
+    if let Some(quorum) = &quorum {
+        quorum.validate()?;
+    }
+    let conflict_of_interest = options.conflict_of_interest.unwrap_or_default();
+
+    let min_duration = u64::try_from(MIN_DURATION.as_nanos()).unwrap();
+    let max_duration = u64::try_from(MAX_DURATION.as_nanos()).unwrap();
+    if duration < min_duration || duration > max_duration {
+        return Err(ButlerError::InvalidDuration {
+            min: min_duration,
+            max: max_duration,
+        });
+    }
+
+    let task_id = STATE.with(|s| {
+        let mut ledger = s.ledger.borrow_mut();
+        let account = Account::from(caller);
+        // Safe because we have checked that the caller is registered above.
+        let balance = *ledger.get(&account).unwrap();
+        let required = SUBMISSION_COST
+            .saturating_add(reward)
+            .saturating_add(pool_amount);
+        if balance < required {
+            return Err(ButlerError::InsufficientBalance { balance, required });
+        }
+        let task_id = s.next_task_id.replace_with(|&mut old| old + 1);
+        // The submission cost is burned while the reward is held in the
+        // task's escrow account until the task is settled.
+        let mut transactions = s.transactions.borrow_mut();
+        let now = time();
+        let mut fee = Transaction::new(
+            TransactionKind::SubmissionFee,
+            Some(account.clone()),
+            None,
+            SUBMISSION_COST,
+            now,
+        );
+        fee.task_id = Some(task_id);
+        execute(&mut ledger, &mut transactions, fee)?;
+        let mut escrow = Transaction::new(
+            TransactionKind::Escrow,
+            Some(account),
+            Some(escrow_account(task_id)),
+            reward,
+            now,
+        );
+        escrow.task_id = Some(task_id);
+        execute(&mut ledger, &mut transactions, escrow)?;
+        if pool_amount > 0 {
+            let mut escrow = Transaction::new(
+                TransactionKind::Escrow,
+                Some(Account::from(caller)),
+                Some(escrow_account(task_id)),
+                pool_amount,
+                now,
+            );
+            escrow.task_id = Some(task_id);
+            execute(&mut ledger, &mut transactions, escrow)?;
+        }
+        Ok(task_id)
+    })?;
+
+    STATE.with(|s| {
+        let mut tasks = s.tasks.borrow_mut();
+        let now = time();
+        let task = TaskInternal {
+            submitter: caller,
+            task_type,
+            payload,
+            answer_deadline: now + duration,
+            vote_deadline: now + duration + review_period,
+            reward,
+            answers: HashSet::new(),
+            status: TaskStatus::Open,
+            cancellation_policy,
+            created_at: now,
+            sponsors: vec![Sponsor {
+                sponsor: caller,
+                amount: reward,
+            }],
+            payout_scheme,
+            payouts: vec![],
+            vote_weighting,
+            language,
+            min_skill_score,
+            commit_reveal,
+            answer_visibility,
+            settlement_mode,
+            dispute_deadline: None,
+            payouts_released: false,
+            dispute: None,
+            voter_pool,
+            voter_rewards: vec![],
+            quorum,
+            review_extended: false,
+            close_reason: None,
+            conflict_of_interest,
+        };
+        schedule(
+            &mut s.deadlines.borrow_mut(),
+            task.next_phase_change(),
+            task_id,
+        );
+        index_insert(&mut s.submitted_tasks.borrow_mut(), caller, task_id);
+        tasks.insert(task_id, task);
+    });
+
+    Ok(task_id)
+}
+
+#[query]
+fn get_task(id: TaskId) -> Result<Task, ButlerError> {
+    let caller = caller();
+    get_task_impl(caller, time(), id)
+}
+
+fn get_task_impl(caller: Principal, now: Timestamp, id: TaskId) -> Result<Task, ButlerError> {
+    STATE.with(|s| {
+        let ledger = s.ledger.borrow();
+        if !ledger.contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let task_map = s.tasks.borrow();
+        let answers_map = s.answers.borrow();
+        match task_map.get(&id) {
+            Some(task_internal_ref) => {
+                let task_internal: TaskInternal = (*task_internal_ref).clone();
+                let mut answers = Vec::new();
+                for ans_id in task_internal.answers.iter() {
+                    match answers_map.get(ans_id) {
+                        Some(ans_ref) => {
+                            let mut answer = (*ans_ref).clone();
+                            let content_hidden =
+                                !task_internal.shows_content(&caller, &answer, now);
+                            if content_hidden {
+                                answer.content = ByteBuf::new();
+                                answer.revisions.clear();
+                            }
+                            answers.push(AnswerWithId{
+                                answer_id: *ans_id,
+                                answer,
+                                content_hidden,
+                            })
+                        }
+                        None => {
+                            ic_cdk::trap(&format!(
+                                "Inconsistent state. AnswerId {} cannot be found",
+                                ans_id
+                            ));
+                        }
+                    }
+                }
+                Ok(Task {
+                    submitter: task_internal.submitter,
+                    task_type: task_internal.task_type,
+                    payload: task_internal.payload,
+                    answer_deadline: task_internal.answer_deadline,
+                    vote_deadline: task_internal.vote_deadline,
+                    reward: task_internal.reward,
+                    answers,
+                    status: task_internal.status,
+                    cancellation_policy: task_internal.cancellation_policy,
+                    created_at: task_internal.created_at,
+                    sponsors: task_internal.sponsors,
+                    payout_scheme: task_internal.payout_scheme,
+                    payouts: task_internal.payouts,
+                    vote_weighting: task_internal.vote_weighting,
+                    language: task_internal.language,
+                    min_skill_score: task_internal.min_skill_score,
+                    commit_reveal: task_internal.commit_reveal,
+                    answer_visibility: task_internal.answer_visibility,
+                    settlement_mode: task_internal.settlement_mode,
+                    dispute_deadline: task_internal.dispute_deadline,
+                    payouts_released: task_internal.payouts_released,
+                    dispute: task_internal.dispute,
+                    voter_pool: task_internal.voter_pool,
+                    voter_rewards: task_internal.voter_rewards,
+                    quorum: task_internal.quorum,
+                    review_extended: task_internal.review_extended,
+                    close_reason: task_internal.close_reason,
+                    conflict_of_interest: task_internal.conflict_of_interest,
+                })
+            }
+            None => Err(ButlerError::TaskNotFound(id)),
+        }
+    })
+}
+
+#[query]
+fn get_all_tasks() -> Result<Vec<ShortTask>, ButlerError> {
+    let caller = caller();
+    let mut tasks = Vec::new();
+
+    STATE.with(|s| {
+        let ledger = s.ledger.borrow();
+        if !ledger.contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let task_map = s.tasks.borrow();
+        let answers = s.answers.borrow();
+        for (task_id_ref, task_internal_ref) in task_map.iter() {
+            tasks.push(task_internal_ref.summary(*task_id_ref, &answers))
+        }
+        Ok(())
+    })?;
+
+    Ok(tasks)
+}
+
+// Lists the tasks matching the filter, newest first. A page starts below
+// the given cursor, or with the newest task if there is none.
+#[query]
+fn list_tasks(
+    filter: Option<TaskFilter>,
+    cursor: Option<TaskId>,
+    limit: u64,
+) -> Result<TaskPage, ButlerError> {
+    list_tasks_impl(caller(), filter, cursor, limit)
+}
+
+fn list_tasks_impl(
+    caller: Principal,
+    filter: Option<TaskFilter>,
+    cursor: Option<TaskId>,
+    limit: u64,
+) -> Result<TaskPage, ButlerError> {
+    let filter = filter.unwrap_or_default();
+    // An empty page could not carry a cursor to the next one, so at least
+    // one entry is returned.
+    let limit = limit.clamp(1, MAX_TASKS_PER_PAGE) as usize;
+    STATE.with(|s| {
+        if !s.ledger.borrow().contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let task_map = s.tasks.borrow();
+        let answers = s.answers.borrow();
+        let range = match cursor {
+            Some(cursor) => task_map.range(..cursor),
+            None => task_map.range(..),
+        };
+        // One task more than requested tells whether there is a next page.
+        let mut tasks: Vec<ShortTask> = range
+            .rev()
+            .filter(|(_, task)| filter.matches(task))
+            .take(limit + 1)
+            .map(|(id, task)| task.summary(*id, &answers))
+            .collect();
+        let next_cursor = if tasks.len() > limit {
+            tasks.truncate(limit);
+            tasks.last().map(|task| task.id)
+        } else {
+            None
+        };
+        Ok(TaskPage { tasks, next_cursor })
+    })
+}
+
+// Lists the tasks submitted by the caller, newest first, like `list_tasks`.
+#[query]
+fn get_my_tasks(cursor: Option<TaskId>, limit: u64) -> Result<TaskPage, ButlerError> {
+    get_my_tasks_impl(caller(), cursor, limit)
+}
+
+fn get_my_tasks_impl(
+    caller: Principal,
+    cursor: Option<TaskId>,
+    limit: u64,
+) -> Result<TaskPage, ButlerError> {
+    STATE.with(|s| {
+        if !s.ledger.borrow().contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let (task_ids, next_cursor) = page(s.submitted_tasks.borrow().get(&caller), cursor, limit);
+        let tasks = s.tasks.borrow();
+        let answers = s.answers.borrow();
+        Ok(TaskPage {
+            tasks: task_ids
+                .into_iter()
+                .filter_map(|id| tasks.get(&id).map(|task| task.summary(id, &answers)))
+                .collect(),
+            next_cursor,
+        })
+    })
+}
+
+// Lists the answers written by the caller, newest first.
+#[query]
+fn get_my_answers(cursor: Option<AnswerId>, limit: u64) -> Result<AnswerPage, ButlerError> {
+    get_my_answers_impl(caller(), cursor, limit)
+}
+
+fn get_my_answers_impl(
+    caller: Principal,
+    cursor: Option<AnswerId>,
+    limit: u64,
+) -> Result<AnswerPage, ButlerError> {
+    STATE.with(|s| {
+        if !s.ledger.borrow().contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let (answer_ids, next_cursor) =
+            page(s.authored_answers.borrow().get(&caller), cursor, limit);
+        let tasks = s.tasks.borrow();
+        let answers = s.answers.borrow();
+        let answer_tasks = s.answer_tasks.borrow();
+        let mut my_answers = vec![];
+        for answer_id in answer_ids {
+            let (answer, task_id, task) =
+                match lookup_answer(&tasks, &answers, &answer_tasks, answer_id) {
+                    Some(found) => found,
+                    None => continue,
+                };
+            let earned = if task.payouts_released {
+                task.payouts
+                    .iter()
+                    .filter(|payout| payout.answer_id == answer_id)
+                    .map(|payout| payout.amount)
+                    .sum()
+            } else {
+                0
+            };
+            my_answers.push(MyAnswer {
+                answer_id,
+                task_id,
+                task_status: task.status.clone(),
+                submission_time: answer.submission_time,
+                earned,
+            });
+        }
+        Ok(AnswerPage {
+            answers: my_answers,
+            next_cursor,
+        })
+    })
+}
+
+// Lists the caller's votes, by answer and newest answer first. Sealed votes
+// are listed as their hashes until they are revealed, and left out if they
+// were not revealed by settlement.
+#[query]
+fn get_my_votes(cursor: Option<AnswerId>, limit: u64) -> Result<VotePage, ButlerError> {
+    get_my_votes_impl(caller(), cursor, limit)
+}
+
+fn get_my_votes_impl(
+    caller: Principal,
+    cursor: Option<AnswerId>,
+    limit: u64,
+) -> Result<VotePage, ButlerError> {
+    STATE.with(|s| {
+        if !s.ledger.borrow().contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let (answer_ids, next_cursor) = page(s.voted_answers.borrow().get(&caller), cursor, limit);
+        let tasks = s.tasks.borrow();
+        let answers = s.answers.borrow();
+        let answer_tasks = s.answer_tasks.borrow();
+        let mut votes = vec![];
+        for answer_id in answer_ids {
+            let (answer, task_id, task) =
+                match lookup_answer(&tasks, &answers, &answer_tasks, answer_id) {
+                    Some(found) => found,
+                    None => continue,
+                };
+            let vote = answer.votes.iter().find(|vote| vote.voter == caller);
+            let commitment = answer
+                .commitments
+                .iter()
+                .find(|commitment| commitment.voter == caller);
+            let (ballot, stake) = match (vote, commitment) {
+                (Some(vote), _) => (Ballot::Choice(vote.choice.clone()), vote.stake),
+                // Sealed votes that were not revealed by settlement were
+                // forfeited.
+                (None, Some(commitment)) if task.status == TaskStatus::InReview => (
+                    Ballot::Commitment(commitment.hash.clone()),
+                    commitment.stake,
+                ),
+                _ => continue,
+            };
+            // Voter rewards are only paid for votes on the winning answer.
+            let earned = if task.winning_answer() == Some(answer_id) {
+                task.voter_rewards
+                    .iter()
+                    .filter(|reward| reward.voter == caller)
+                    .map(|reward| reward.amount)
+                    .sum()
+            } else {
+                0
+            };
+            votes.push(MyVote {
+                answer_id,
+                task_id,
+                task_status: task.status.clone(),
+                ballot,
+                stake,
+                earned,
+            });
+        }
+        Ok(VotePage { votes, next_cursor })
+    })
+}
+
+// Returns up to `limit` IDs of a per-principal index below the cursor,
+// newest first, and the cursor for the next page if there are more.
+fn page(ids: Option<&BTreeSet<u64>>, cursor: Option<u64>, limit: u64) -> (Vec<u64>, Option<u64>) {
+    let ids = match ids {
+        Some(ids) => ids,
+        None => return (vec![], None),
+    };
+    // Like `list_tasks_impl`, this treats a limit of 0 as 1.
+    let limit = limit.clamp(1, MAX_TASKS_PER_PAGE) as usize;
+    let range = match cursor {
+        Some(cursor) => ids.range(..cursor),
+        None => ids.range(..),
+    };
+    let mut page: Vec<u64> = range.rev().take(limit + 1).copied().collect();
+    if page.len() > limit {
+        page.truncate(limit);
+        let next_cursor = page.last().copied();
+        (page, next_cursor)
+    } else {
+        (page, None)
+    }
+}
+
+// Finds an answer together with its task. The indexes are kept in sync with
+// the answers, but a miss is skipped rather than failing a whole page.
+fn lookup_answer<'a>(
+    tasks: &'a BTreeMap<TaskId, TaskInternal>,
+    answers: &'a HashMap<AnswerId, Answer>,
+    answer_tasks: &HashMap<AnswerId, TaskId>,
+    answer_id: AnswerId,
+) -> Option<(&'a Answer, TaskId, &'a TaskInternal)> {
+    let answer = answers.get(&answer_id)?;
+    let task_id = *answer_tasks.get(&answer_id)?;
+    let task = tasks.get(&task_id)?;
+    Some((answer, task_id, task))
+}
+
+fn index_insert(index: &mut HashMap<Principal, BTreeSet<u64>>, principal: Principal, id: u64) {
+    index.entry(principal).or_default().insert(id);
+}
+
+fn index_remove(index: &mut HashMap<Principal, BTreeSet<u64>>, principal: Principal, id: u64) {
+    if let Some(ids) = index.get_mut(&principal) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            index.remove(&principal);
+        }
+    }
+}
+
+#[query]
+fn get_balance() -> Result<Amount, ButlerError> {
+    let caller = caller();
+
+    STATE.with(|s| {
+        let ledger = s.ledger.borrow();
+        ledger
+            .get(&Account::from(caller))
+            .copied()
+            .ok_or(ButlerError::NotRegistered)
+    })
+}
+
+#[update]
+fn transfer(to: Principal, amount: Amount, memo: Option<Memo>) -> Result<BlockIndex, ButlerError> {
+    let caller = caller();
+    transfer_impl(
+        Account::from(caller),
+        time(),
+        Account::from(to),
+        amount,
+        memo,
+        None,
+    )
+}
+
+fn transfer_impl(
+    from: Account,
+    now: Timestamp,
+    to: Account,
+    amount: Amount,
+    memo: Option<Memo>,
+    created_at_time: Option<Timestamp>,
+) -> Result<BlockIndex, ButlerError> {
+    if let Some(memo) = &memo {
+        if memo.len() > MAX_MEMO_SIZE {
+            return Err(ButlerError::PayloadTooLarge {
+                max_size: MAX_MEMO_SIZE as u64,
+                size: memo.len() as u64,
+            });
+        }
+    }
+    from.validate()?;
+    to.validate()?;
+
+    if to.owner == canister_id() {
+        return Err(ButlerError::ReservedAccount(to));
+    }
+
+    STATE.with(|s| {
+        let mut ledger = s.ledger.borrow_mut();
+        let mut transaction =
+            Transaction::new(TransactionKind::Transfer, Some(from), Some(to), amount, now);
+        transaction.memo = memo;
+        transaction.created_at_time = created_at_time;
+        execute(&mut ledger, &mut s.transactions.borrow_mut(), transaction)
+    })
+}
+
+#[query]
+fn icrc1_name() -> String {
+    TOKEN_NAME.to_string()
+}
+
+#[query]
+fn icrc1_symbol() -> String {
+    TOKEN_SYMBOL.to_string()
+}
+
+#[query]
+fn icrc1_decimals() -> u8 {
+    TOKEN_DECIMALS
+}
+
+#[query]
+fn icrc1_fee() -> Nat {
+    Nat::from(TRANSFER_FEE)
+}
+
+#[query]
+fn icrc1_metadata() -> Vec<(String, MetadataValue)> {
+    vec![
+        (
+            "icrc1:name".to_string(),
+            MetadataValue::Text(TOKEN_NAME.to_string()),
+        ),
+        (
+            "icrc1:symbol".to_string(),
+            MetadataValue::Text(TOKEN_SYMBOL.to_string()),
+        ),
+        (
+            "icrc1:decimals".to_string(),
+            MetadataValue::Nat(Nat::from(TOKEN_DECIMALS)),
+        ),
+        (
+            "icrc1:fee".to_string(),
+            MetadataValue::Nat(Nat::from(TRANSFER_FEE)),
+        ),
+    ]
+}
+
+// Includes the tokens held in task escrow accounts.
+#[query]
+fn icrc1_total_supply() -> Nat {
+    STATE.with(|s| {
+        let ledger = s.ledger.borrow();
+        Nat::from(ledger.values().map(|amount| *amount as u128).sum::<u128>())
+    })
+}
+
+#[query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    match icrc1_balance_of_impl(account) {
+        Ok(balance) => balance,
+        Err(err) => ic_cdk::trap(&format!("{:?}", err)),
+    }
+}
+
+fn icrc1_balance_of_impl(account: Account) -> Result<Nat, ButlerError> {
+    account.validate()?;
+    STATE.with(|s| {
+        let ledger = s.ledger.borrow();
+        Ok(Nat::from(
+            ledger.get(&account.normalized()).copied().unwrap_or(0),
+        ))
+    })
+}
+
+#[update]
+fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    let caller = caller();
+    icrc1_transfer_impl(caller, time(), arg)
+}
+
+fn icrc1_transfer_impl(
+    caller: Principal,
+    now: Timestamp,
+    arg: TransferArg,
+) -> Result<Nat, TransferError> {
+    if let Some(fee) = &arg.fee {
+        if u64::try_from(&fee.0) != Ok(TRANSFER_FEE) {
+            return Err(TransferError::BadFee {
+                expected_fee: Nat::from(TRANSFER_FEE),
+            });
+        }
+    }
+
+    let from = Account {
+        owner: caller,
+        subaccount: arg.from_subaccount,
+    }
+    .normalized();
+    let to = arg.to.normalized();
+    let memo = arg.memo;
+    // Amounts that do not fit into the ledger cannot be covered by any balance.
+    let amount = match u64::try_from(&arg.amount.0) {
+        Ok(amount) => amount,
+        Err(_) => {
+            let balance = STATE.with(|s| s.ledger.borrow().get(&from).copied().unwrap_or(0));
+            return Err(TransferError::InsufficientFunds {
+                balance: Nat::from(balance),
+            });
+        }
+    };
+
+    // Deduplicate transfers that carry a creation time, as described in the
+    // ICRC-1 standard.
+    if let Some(created_at_time) = arg.created_at_time {
+        let window = u64::try_from((TX_WINDOW + PERMITTED_DRIFT).as_nanos()).unwrap();
+        let drift = u64::try_from(PERMITTED_DRIFT.as_nanos()).unwrap();
+        if created_at_time.saturating_add(window) < now {
+            return Err(TransferError::TooOld);
+        }
+        if created_at_time > now.saturating_add(drift) {
+            return Err(TransferError::CreatedInFuture { ledger_time: now });
+        }
+        let duplicate = STATE.with(|s| {
+            let transactions = s.transactions.borrow();
+            transactions
+                .iter()
+                .rev()
+                .take_while(|tx| tx.timestamp.saturating_add(window) >= now)
+                .find(|tx| {
+                    tx.kind == TransactionKind::Transfer
+                        && tx.created_at_time == Some(created_at_time)
+                        && tx.from.as_ref() == Some(&from)
+                        && tx.to.as_ref() == Some(&to)
+                        && tx.amount == amount
+                        && tx.memo == memo
+                })
+                .map(|tx| tx.index)
+        });
+        if let Some(index) = duplicate {
+            return Err(TransferError::Duplicate {
+                duplicate_of: Nat::from(index),
+            });
+        }
+    }
+
+    transfer_impl(from, now, to, amount, memo, arg.created_at_time)
+        .map(Nat::from)
+        .map_err(TransferError::from)
+}
+
+// Tokens are granted on registration and burned by the canister itself, so
+// there is no minting account.
+#[query]
+fn icrc1_minting_account() -> Option<Account> {
+    None
+}
+
+#[query]
+fn icrc1_supported_standards() -> Vec<StandardRecord> {
+    vec![StandardRecord {
+        name: "ICRC-1".to_string(),
+        url: "https://github.com/dfinity/ICRC-1".to_string(),
+    }]
+}
+
+#[query]
+fn get_transactions(start: BlockIndex, length: u64) -> TransactionRange {
+    STATE.with(|s| {
+        let transactions = s.transactions.borrow();
+        TransactionRange {
+            total: transactions.len() as u64,
+            transactions: transactions
+                .iter()
+                .skip(start as usize)
+                .take(length.min(MAX_TRANSACTIONS_PER_PAGE) as usize)
+                .cloned()
+                .collect(),
+        }
+    })
+}
+
+// Pages through the transactions that move tokens from or to any account
+// of `principal`, oldest first.
+#[query]
+fn get_principal_transactions(principal: Principal, start: u64, length: u64) -> TransactionRange {
+    STATE.with(|s| {
+        let transactions = s.transactions.borrow();
+        let history = transactions.history(&principal);
+        TransactionRange {
+            total: history.len() as u64,
+            transactions: history
+                .iter()
+                .skip(start as usize)
+                .take(length.min(MAX_TRANSACTIONS_PER_PAGE) as usize)
+                .map(|index| transactions[*index as usize].clone())
+                .collect(),
+        }
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+struct ReputationEntry {
+    principal: Principal,
+    reputation: i64,
+}
+
+// Principals that never took part in a settled task have no reputation.
+#[query]
+fn get_reputation(principal: Principal) -> i64 {
+    STATE.with(|s| s.reputation.borrow().get(&principal).copied().unwrap_or(0))
+}
+
+// Returns the principals with the highest reputation, best first.
+#[query]
+fn get_leaderboard(limit: u64) -> Vec<ReputationEntry> {
+    STATE.with(|s| {
+        leaderboard(
+            &s.reputation.borrow(),
+            limit.min(MAX_LEADERBOARD_SIZE) as usize,
+        )
+    })
+}
+
+fn leaderboard(reputation: &HashMap<Principal, i64>, limit: usize) -> Vec<ReputationEntry> {
+    let mut entries: Vec<ReputationEntry> = reputation
+        .iter()
+        .map(|(principal, reputation)| ReputationEntry {
+            principal: *principal,
+            reputation: *reputation,
+        })
+        .collect();
+    entries.sort_by_key(|entry| (std::cmp::Reverse(entry.reputation), entry.principal));
+    entries.truncate(limit);
+    entries
+}
+
+// Returns the skill scores of a principal, highest first.
+#[query]
+fn get_skills(principal: Principal) -> Vec<SkillScore> {
+    STATE.with(|s| {
+        let mut scores: Vec<SkillScore> = s
+            .skills
+            .borrow()
+            .get(&principal)
+            .into_iter()
+            .flat_map(|skills| skills.iter())
+            .map(|(skill, score)| SkillScore {
+                skill: skill.clone(),
+                score: *score,
+            })
+            .collect();
+        scores.sort_by_key(|score| std::cmp::Reverse(score.score));
+        scores
+    })
+}
+
+#[update]
+fn answer_task(task_id: TaskId, content: Content) -> Result<AnswerId, ButlerError> {
+    let caller = caller();
+    STATE.with(|s| {
+        let ledger = s.ledger.borrow();
+
+        // Precondition: caller is a principal on the ledger
+        if !ledger.contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        Ok(())
+    })?;
+
+    STATE.with(|s| {
+        let mut tasks = s.tasks.borrow_mut();
+        let mut answers = s.answers.borrow_mut();
+
+        match tasks.get_mut(&task_id) {
+            // the task ID does not exist
+            None => Err(ButlerError::TaskNotFound(task_id)),
+            Some(task) => {
+                // Precondition: there are less than max_answers for taskID
+                if task.answers.len() >= MAX_NUMBER_ANSWERS {
+                    return Err(ButlerError::TooManyAnswers {
+                        max: MAX_NUMBER_ANSWERS as u64,
+                    });
+                }
+
+                // Precondition: the task is still open
+                if task.status != TaskStatus::Open {
+                    return Err(ButlerError::TaskNotOpen(task_id));
+                }
+
+                // Precondition: the answer deadline for the task has not expired
+                if task.answer_deadline < time() {
+                    return Err(ButlerError::DeadlinePassed {
+                        deadline: task.answer_deadline,
+                    });
+                }
+
+                validate_content(task, &content)?;
+
+                // Precondition: the caller is skilled enough if the submitter requires it
+                if let Some(required) = task.min_skill_score {
+                    let actual = s
+                        .skills
+                        .borrow()
+                        .get(&caller)
+                        .and_then(|skills| skills.get(&task.required_skill()))
+                        .copied()
+                        .unwrap_or(0);
+                    if actual < required {
+                        return Err(ButlerError::InsufficientSkill { required, actual });
+                    }
+                }
+
+                // Precondition: the caller hasn’t submitted an answer for this task
+                for answer_id in task.answers.iter() {
+                    match answers.get(answer_id){
+                        Some(answer) => {
+                            if caller == answer.submitter {
+                                return Err(ButlerError::DuplicateAnswer(*answer_id));
+                            }
+                        },
+                        // this is a case which should not occur, but let's catch it just to be sure
+                        None => {
+                            ic_cdk::trap(&format!(
+                                "The answer with ID {} was listed in task with ID {} even though there is no\
+                        such answer recorded.",
+                                answer_id, task_id));
+                        }
+                    }
+                }
+
+                // Now that all the preconditions are met, make the new answer and submit it
+                let answer_id = s.next_answer_id.replace_with(|&mut old| old + 1);
+                task.answers.insert(answer_id);
+                s.answer_tasks.borrow_mut().insert(answer_id, task_id);
+                index_insert(&mut s.authored_answers.borrow_mut(), caller, answer_id);
+                answers.insert(
+                    answer_id,
+                    Answer {
+                        submitter: caller,
+                        submission_time: time(),
+                        content,
+                        votes: vec![],
+                        commitments: vec![],
+                        vote_changes: vec![],
+                        revisions: vec![],
+                    },
+                );
+                Ok(answer_id)
+            }
+        }
+    })
+}
+
+// Votes on an answer. Tasks with stake-weighted voting require the voter to
+// lock a stake in the task's escrow, which is released at settlement. Tasks
+// with commit-reveal voting take sealed votes, which lock the penalty
+// deposit until they are revealed.
+#[update]
+fn vote(
+    answer_id: AnswerId,
+    task_id: TaskId,
+    ballot: Ballot,
+    stake: Option<Amount>,
+) -> Result<(), ButlerError> {
+    vote_impl(caller(), time(), answer_id, task_id, ballot, stake)
+}
+
+fn vote_impl(
+    caller: Principal,
+    now: Timestamp,
+    answer_id: AnswerId,
+    task_id: TaskId,
+    ballot: Ballot,
+    stake: Option<Amount>,
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let mut ledger = s.ledger.borrow_mut();
+        let mut answers = s.answers.borrow_mut();
+        let mut tasks = s.tasks.borrow_mut();
+
+        // Precondition: caller is a principal on the ledger
+        if !ledger.contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        // Whether the caller answered the task, which needs all of its answers.
+        let is_answerer = match tasks.get(&task_id) {
+            Some(task) => task
+                .answers
+                .iter()
+                .filter_map(|id| answers.get(id))
+                .any(|answer| answer.submitter == caller),
+            None => false,
+        };
+        match answers.get_mut(&answer_id) {
+            // Precondition: the answerID exists
+            None => Err(ButlerError::AnswerNotFound(answer_id)),
+            Some(answer) => {
+                match tasks.get_mut(&task_id) {
+                    // Precondition: the taskID exists
+                    None => Err(ButlerError::TaskNotFound(task_id)),
+                    Some(task) => {
+                        check_voting_open(task, task_id, answer_id, now)?;
+                        // Precondition: the caller has not voted on this answer yet
+                        if answer
+                            .votes
+                            .iter()
+                            .any(|existing_vote| existing_vote.voter == caller)
+                            || answer
+                                .commitments
+                                .iter()
+                                .any(|commitment| commitment.voter == caller)
+                        {
+                            return Err(ButlerError::AlreadyVoted(answer_id));
+                        }
+                        // Precondition: the caller has no conflict of interest
+                        if answer.submitter == caller {
+                            return Err(ButlerError::OwnAnswer(answer_id));
+                        }
+                        if is_answerer && task.conflict_of_interest.exclude_answerers {
+                            return Err(ButlerError::AnswererCannotVote(task_id));
+                        }
+                        if task.submitter == caller && task.conflict_of_interest.exclude_submitter {
+                            return Err(ButlerError::SubmitterCannotVote(task_id));
+                        }
+                        let deposit = check_ballot(&ballot, task)?;
+                        // Precondition: a stake is given if and only if votes are weighted by stake
+                        let stake = stake.unwrap_or(0);
+                        let weight = match task.vote_weighting {
+                            VoteWeighting::Equal if stake == 0 => 1,
+                            VoteWeighting::Stake if stake > 0 => stake,
+                            VoteWeighting::Reputation if stake == 0 => {
+                                let reputation =
+                                    s.reputation.borrow().get(&caller).copied().unwrap_or(0);
+                                1 + reputation.max(0) as u64
+                            }
+                            _ => return Err(ButlerError::InvalidAmount),
+                        };
+                        let locked = stake
+                            .checked_add(deposit)
+                            .ok_or(ButlerError::InvalidAmount)?;
+                        if locked > 0 {
+                            let mut transaction = Transaction::new(
+                                TransactionKind::Stake,
+                                Some(Account::from(caller)),
+                                Some(escrow_account(task_id)),
+                                locked,
+                                now,
+                            );
+                            transaction.task_id = Some(task_id);
+                            execute(&mut ledger, &mut s.transactions.borrow_mut(), transaction)?;
+                        }
+                        // At this point all the preconditions are met and we can update the vote
+                        match ballot {
+                            Ballot::Choice(choice) => answer.votes.push(Vote {
+                                voter: caller,
+                                choice,
+                                weight,
+                                stake,
+                            }),
+                            Ballot::Commitment(hash) => answer.commitments.push(Commitment {
+                                voter: caller,
+                                hash,
+                                weight,
+                                stake,
+                                deposit,
+                            }),
+                        }
+                        index_insert(&mut s.voted_answers.borrow_mut(), caller, answer_id);
+                        Ok(())
+                    }
+                }
+            }
+        }
+    })
+}
+
+// Checks the content of an answer to the given task.
+fn validate_content(task: &TaskInternal, content: &[u8]) -> Result<(), ButlerError> {
+    // Precondition: the solution's size is less than MAX_CONTENT_SIZE, or
+    // MAX_IMAGE_SIZE for images
+    let max_size = match task.task_type {
+        TaskType::TranslateText => MAX_CONTENT_SIZE,
+        TaskType::EditImage => MAX_IMAGE_SIZE,
+    };
+    if content.len() > max_size {
+        return Err(ButlerError::PayloadTooLarge {
+            max_size: max_size as u64,
+            size: content.len() as u64,
+        });
+    }
+    // Precondition: answers to image tasks are images of the requested format
+    if task.task_type == TaskType::EditImage {
+        // Safe because the payload was validated in `submit_task`.
+        let input = Decode!(&task.payload, EditImageInput).unwrap();
+        input
+            .validate_answer(content)
+            .map_err(ButlerError::InvalidAnswer)?;
+    }
+    Ok(())
+}
+
+// Looks up an answer for its author while the answer's task still takes
+// answers.
+fn check_answer_editable(
+    s: &State,
+    caller: Principal,
+    now: Timestamp,
+    answer_id: AnswerId,
+) -> Result<TaskId, ButlerError> {
+    let answers = s.answers.borrow();
+    let answer = answers
+        .get(&answer_id)
+        .ok_or(ButlerError::AnswerNotFound(answer_id))?;
+    // Precondition: the caller wrote the answer
+    if answer.submitter != caller {
+        return Err(ButlerError::NotAnswerAuthor(answer_id));
+    }
+    let task_id = *s.answer_tasks.borrow().get(&answer_id).unwrap_or_else(|| {
+        ic_cdk::trap(&format!(
+            "Inconsistent state. AnswerId {} is not listed in any task",
+            answer_id
+        ))
+    });
+    let tasks = s.tasks.borrow();
+    let task = tasks
+        .get(&task_id)
+        .ok_or(ButlerError::TaskNotFound(task_id))?;
+    // Precondition: the task is still open
+    if task.status != TaskStatus::Open {
+        return Err(ButlerError::TaskNotOpen(task_id));
+    }
+    if task.answer_deadline < now {
+        return Err(ButlerError::DeadlinePassed {
+            deadline: task.answer_deadline,
+        });
+    }
+    Ok(task_id)
+}
+
+// Replaces the content of the caller's answer while its task takes answers.
+// The previous content is kept as a revision. Since votes are only taken
+// once the answer deadline has passed, an edited answer has no votes yet.
+#[update]
+fn update_answer(answer_id: AnswerId, content: Content) -> Result<(), ButlerError> {
+    update_answer_impl(caller(), time(), answer_id, content)
+}
+
+fn update_answer_impl(
+    caller: Principal,
+    now: Timestamp,
+    answer_id: AnswerId,
+    content: Content,
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let task_id = check_answer_editable(s, caller, now, answer_id)?;
+        // Safe because the task was found when checking the preconditions.
+        validate_content(&s.tasks.borrow()[&task_id], &content)?;
+
+        let mut answers = s.answers.borrow_mut();
+        // Safe because the answer was found when checking the preconditions.
+        let answer = answers.get_mut(&answer_id).unwrap();
+        let previous = std::mem::replace(&mut answer.content, content);
+        answer.revisions.push(Revision {
+            content: previous,
+            replaced_at: now,
+        });
+        Ok(())
+    })
+}
+
+// Withdraws the caller's answer while its task takes answers, which frees
+// its slot for another answer.
+#[update]
+fn withdraw_answer(answer_id: AnswerId) -> Result<(), ButlerError> {
+    withdraw_answer_impl(caller(), time(), answer_id)
+}
+
+fn withdraw_answer_impl(
+    caller: Principal,
+    now: Timestamp,
+    answer_id: AnswerId,
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let task_id = check_answer_editable(s, caller, now, answer_id)?;
+        // Safe because the task was found when checking the preconditions.
+        s.tasks
+            .borrow_mut()
+            .get_mut(&task_id)
+            .unwrap()
+            .answers
+            .remove(&answer_id);
+        s.answer_tasks.borrow_mut().remove(&answer_id);
+        index_remove(&mut s.authored_answers.borrow_mut(), caller, answer_id);
+        s.answers.borrow_mut().remove(&answer_id);
+        Ok(())
+    })
+}
+
+// Checks that votes on the given answer are accepted, i.e. that the answer
+// belongs to the task and that the task is in its review phase.
+fn check_voting_open(
+    task: &TaskInternal,
+    task_id: TaskId,
+    answer_id: AnswerId,
+    now: Timestamp,
+) -> Result<(), ButlerError> {
+    // Precondition: answerID is an answer for the given task
+    if !task.answers.contains(&answer_id) {
+        return Err(ButlerError::AnswerNotInTask { answer_id, task_id });
+    }
+    // Precondition: the task has not been settled or cancelled
+    if !matches!(task.status, TaskStatus::Open | TaskStatus::InReview) {
+        return Err(ButlerError::TaskNotOpen(task_id));
+    }
+    // Precondition: the task is in its review phase
+    if now <= task.answer_deadline {
+        return Err(ButlerError::VotingNotOpen {
+            opens_at: task.answer_deadline,
+        });
+    }
+    if task.vote_deadline < now {
+        return Err(ButlerError::DeadlinePassed {
+            deadline: task.vote_deadline,
+        });
+    }
+    Ok(())
+}
+
+// Checks that a ballot is sealed if and only if the task uses commit-reveal
+// voting and returns the deposit a sealed vote locks.
+fn check_ballot(ballot: &Ballot, task: &TaskInternal) -> Result<Amount, ButlerError> {
+    match (ballot, &task.commit_reveal) {
+        (Ballot::Choice(_), None) => Ok(0),
+        (Ballot::Commitment(hash), Some(commit_reveal)) => {
+            if hash.len() != COMMITMENT_SIZE {
+                return Err(ButlerError::InvalidBallot(format!(
+                    "A sealed vote must be {} bytes long, but it is {}.",
+                    COMMITMENT_SIZE,
+                    hash.len()
+                )));
+            }
+            Ok(commit_reveal.penalty)
+        }
+        (Ballot::Choice(_), Some(_)) => Err(ButlerError::InvalidBallot(
+            "Votes on this task must be sealed.".to_string(),
+        )),
+        (Ballot::Commitment(_), None) => Err(ButlerError::InvalidBallot(
+            "Votes on this task cannot be sealed.".to_string(),
+        )),
+    }
+}
+
+// Replaces the caller's vote on an answer while the task takes votes. The
+// weight and stake of the vote stay the same.
+#[update]
+fn change_vote(answer_id: AnswerId, task_id: TaskId, ballot: Ballot) -> Result<(), ButlerError> {
+    change_vote_impl(caller(), time(), answer_id, task_id, ballot)
+}
+
+fn change_vote_impl(
+    caller: Principal,
+    now: Timestamp,
+    answer_id: AnswerId,
+    task_id: TaskId,
+    ballot: Ballot,
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        // Precondition: caller is a principal on the ledger
+        if !s.ledger.borrow().contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let tasks = s.tasks.borrow();
+        let mut answers = s.answers.borrow_mut();
+        let task = tasks
+            .get(&task_id)
+            .ok_or(ButlerError::TaskNotFound(task_id))?;
+        let answer = answers
+            .get_mut(&answer_id)
+            .ok_or(ButlerError::AnswerNotFound(answer_id))?;
+
+        check_voting_open(task, task_id, answer_id, now)?;
+        check_ballot(&ballot, task)?;
+        // Precondition: the caller has voted on this answer
+        let previous = match &ballot {
+            Ballot::Choice(choice) => {
+                let vote = answer
+                    .votes
+                    .iter_mut()
+                    .find(|vote| vote.voter == caller)
+                    .ok_or(ButlerError::VoteNotFound(answer_id))?;
+                Ballot::Choice(std::mem::replace(&mut vote.choice, choice.clone()))
+            }
+            Ballot::Commitment(hash) => {
+                let commitment = answer
+                    .commitments
+                    .iter_mut()
+                    .find(|commitment| commitment.voter == caller)
+                    .ok_or(ButlerError::VoteNotFound(answer_id))?;
+                Ballot::Commitment(std::mem::replace(&mut commitment.hash, hash.clone()))
+            }
+        };
+        answer.vote_changes.push(VoteChange {
+            voter: caller,
+            previous,
+            current: Some(ballot),
+            timestamp: now,
+        });
+        Ok(())
+    })
+}
+
+// Removes the caller's vote on an answer while the task takes votes and
+// returns the tokens it locked.
+#[update]
+fn retract_vote(answer_id: AnswerId, task_id: TaskId) -> Result<(), ButlerError> {
+    retract_vote_impl(caller(), time(), answer_id, task_id)
+}
+
+fn retract_vote_impl(
+    caller: Principal,
+    now: Timestamp,
+    answer_id: AnswerId,
+    task_id: TaskId,
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        // Precondition: caller is a principal on the ledger
+        if !s.ledger.borrow().contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let tasks = s.tasks.borrow();
+        let mut answers = s.answers.borrow_mut();
+        let task = tasks
+            .get(&task_id)
+            .ok_or(ButlerError::TaskNotFound(task_id))?;
+        let answer = answers
+            .get_mut(&answer_id)
+            .ok_or(ButlerError::AnswerNotFound(answer_id))?;
+
+        check_voting_open(task, task_id, answer_id, now)?;
+        // Precondition: the caller has voted on this answer
+        let (previous, locked) =
+            if let Some(position) = answer.votes.iter().position(|vote| vote.voter == caller) {
+                let vote = answer.votes.remove(position);
+                (Ballot::Choice(vote.choice), vote.stake)
+            } else if let Some(position) = answer
+                .commitments
+                .iter()
+                .position(|commitment| commitment.voter == caller)
+            {
+                let commitment = answer.commitments.remove(position);
+                (
+                    Ballot::Commitment(commitment.hash),
+                    commitment.stake + commitment.deposit,
+                )
+            } else {
+                return Err(ButlerError::VoteNotFound(answer_id));
+            };
+
+        trap_on_error(pay_from_escrow(
+            &mut s.ledger.borrow_mut(),
+            &mut s.transactions.borrow_mut(),
+            task_id,
+            TransactionKind::StakeRelease,
+            caller,
+            locked,
+            now,
+        ));
+        answer.vote_changes.push(VoteChange {
+            voter: caller,
+            previous,
+            current: None,
+            timestamp: now,
+        });
+        index_remove(&mut s.voted_answers.borrow_mut(), caller, answer_id);
+        Ok(())
+    })
+}
+
+// Reveals a sealed vote once the task's vote deadline has passed. The vote
+// then counts at settlement and its deposit is returned.
+#[update]
+fn reveal_vote(
+    answer_id: AnswerId,
+    task_id: TaskId,
+    choice: Choice,
+    salt: ByteBuf,
+) -> Result<(), ButlerError> {
+    reveal_vote_impl(caller(), time(), answer_id, task_id, choice, salt)
+}
+
+fn reveal_vote_impl(
+    caller: Principal,
+    now: Timestamp,
+    answer_id: AnswerId,
+    task_id: TaskId,
+    choice: Choice,
+    salt: ByteBuf,
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let tasks = s.tasks.borrow();
+        let mut answers = s.answers.borrow_mut();
+        let task = tasks
+            .get(&task_id)
+            .ok_or(ButlerError::TaskNotFound(task_id))?;
+        let answer = answers
+            .get_mut(&answer_id)
+            .ok_or(ButlerError::AnswerNotFound(answer_id))?;
+
+        // Precondition: answerID is an answer for the given task
+        if !task.answers.contains(&answer_id) {
+            return Err(ButlerError::AnswerNotInTask { answer_id, task_id });
+        }
+        // Precondition: the task has not been settled or cancelled
+        if !matches!(task.status, TaskStatus::Open | TaskStatus::InReview) {
+            return Err(ButlerError::TaskNotOpen(task_id));
+        }
+        // Precondition: the task is in its reveal period
+        if task.commit_reveal.is_none() {
+            return Err(ButlerError::InvalidBallot(
+                "Votes on this task cannot be sealed.".to_string(),
+            ));
+        }
+        if now <= task.vote_deadline {
+            return Err(ButlerError::RevealNotOpen {
+                opens_at: task.vote_deadline,
+            });
+        }
+        if task.settlement_time() < now {
+            return Err(ButlerError::DeadlinePassed {
+                deadline: task.settlement_time(),
+            });
+        }
+        // Precondition: the vote matches the caller's sealed vote
+        let position = answer
+            .commitments
+            .iter()
+            .position(|commitment| commitment.voter == caller)
+            .ok_or(ButlerError::CommitmentNotFound(answer_id))?;
+        let hash = commitment_hash(&caller, &choice, &salt);
+        if answer.commitments[position].hash.as_slice() != hash.as_slice() {
+            return Err(ButlerError::CommitmentMismatch(answer_id));
+        }
+
+        let commitment = answer.commitments.remove(position);
+        trap_on_error(pay_from_escrow(
+            &mut s.ledger.borrow_mut(),
+            &mut s.transactions.borrow_mut(),
+            task_id,
+            TransactionKind::StakeRelease,
+            caller,
+            commitment.deposit,
+            now,
+        ));
+        answer.votes.push(Vote {
+            voter: caller,
+            choice,
+            weight: commitment.weight,
+            stake: commitment.stake,
+        });
+        Ok(())
+    })
+}
+
+// Accepts an answer to a task in acceptance mode. This pays the whole reward
+// to the answer's author and closes the task. Votes cast so far no longer
+// matter and their stakes and deposits are returned.
+#[update]
+fn accept_answer(task_id: TaskId, answer_id: AnswerId) -> Result<(), ButlerError> {
+    accept_answer_impl(caller(), time(), task_id, answer_id)
+}
+
+fn accept_answer_impl(
+    caller: Principal,
+    now: Timestamp,
+    task_id: TaskId,
+    answer_id: AnswerId,
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let mut tasks = s.tasks.borrow_mut();
+        let answers = s.answers.borrow();
+        let task = tasks
+            .get_mut(&task_id)
+            .ok_or(ButlerError::TaskNotFound(task_id))?;
+
+        // Precondition: only the submitter can accept an answer
+        if task.submitter != caller {
+            return Err(ButlerError::NotSubmitter(task_id));
+        }
+        // Precondition: answerID is an answer for the given task
+        if !task.answers.contains(&answer_id) {
+            return Err(ButlerError::AnswerNotInTask { answer_id, task_id });
+        }
+        // Precondition: the task has not been settled or cancelled
+        if !matches!(task.status, TaskStatus::Open | TaskStatus::InReview) {
+            return Err(ButlerError::TaskNotOpen(task_id));
+        }
+        // Precondition: the task is in acceptance mode and its grace period has not ended
+        let acceptance_deadline = task
+            .acceptance_deadline()
+            .ok_or(ButlerError::AcceptanceNotEnabled(task_id))?;
+        if acceptance_deadline < now {
+            return Err(ButlerError::DeadlinePassed {
+                deadline: acceptance_deadline,
+            });
+        }
+        let answer = answers.get(&answer_id).unwrap_or_else(|| {
+            ic_cdk::trap(&format!(
+                "Inconsistent state. AnswerId {} cannot be found",
+                answer_id
+            ))
+        });
+
+        unschedule(
+            &mut s.deadlines.borrow_mut(),
+            task.next_phase_change(),
+            task_id,
+        );
+        task.status = TaskStatus::Closed;
+        task.close_reason = Some(CloseReason::Accepted);
+        task.payouts = vec![Payout {
+            answer_id,
+            recipient: answer.submitter,
+            amount: task.reward,
+        }];
+        task.payouts_released = true;
+        let ranked = rank_answers(task_answers(&answers, task_id, task));
+        update_reputation(
+            &mut s.reputation.borrow_mut(),
+            &ranked,
+            &task.payouts,
+            &answer.votes,
+        );
+        update_skills(
+            &mut s.skills.borrow_mut(),
+            &task.skills(),
+            &ranked,
+            &task.payouts,
+        );
+        let mut ledger = s.ledger.borrow_mut();
+        let mut transactions = s.transactions.borrow_mut();
+        trap_on_error(pay_out(&mut ledger, &mut transactions, task_id, task, now));
+        trap_on_error(release_stakes(
+            &mut ledger,
+            &mut transactions,
+            &answers,
+            task_id,
+            task,
+            false,
+            now,
+        ));
+        trap_on_error(distribute_voter_pool(
+            &mut ledger,
+            &mut transactions,
+            &answers,
+            task_id,
+            task,
+            now,
+        ));
+        Ok(())
+    })
+}
+
+// Disputes the outcome of a task settled by votes before its payouts are
+// released. This locks a bond and draws a jury among high-reputation
+// principals who did not take part in the task. The payouts stay in escrow
+// until the jury has decided.
+#[update]
+async fn open_dispute(task_id: TaskId, reason: String) -> Result<(), ButlerError> {
+    let caller = caller();
+    // Check the preconditions before asking for randomness so that invalid
+    // disputes fail right away.
+    STATE.with(|s| dispute_candidates(s, caller, time(), task_id, &reason))?;
+    let (seed,): (Vec<u8>,) =
+        ic_cdk::api::call::call(Principal::management_canister(), "raw_rand", ())
+            .await
+            .map_err(|(code, message)| {
+                ButlerError::RandomnessUnavailable(format!("{:?}: {}", code, message))
+            })?;
+    // The state may have changed while waiting for the randomness, so the
+    // preconditions are checked again.
+    open_dispute_impl(caller, time(), task_id, reason, &seed)
+}
+
+fn open_dispute_impl(
+    caller: Principal,
+    now: Timestamp,
+    task_id: TaskId,
+    reason: String,
+    seed: &[u8],
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let candidates = dispute_candidates(s, caller, now, task_id, &reason)?;
+        let jury = select_jury(candidates, seed, JURY_SIZE);
+
+        let mut transaction = Transaction::new(
+            TransactionKind::DisputeBond,
+            Some(Account::from(caller)),
+            Some(escrow_account(task_id)),
+            DISPUTE_BOND,
+            now,
+        );
+        transaction.task_id = Some(task_id);
+        execute(
+            &mut s.ledger.borrow_mut(),
+            &mut s.transactions.borrow_mut(),
+            transaction,
+        )?;
+
+        let mut tasks = s.tasks.borrow_mut();
+        // Safe because the task was found when checking the preconditions.
+        let task = tasks.get_mut(&task_id).unwrap();
+        let mut deadlines = s.deadlines.borrow_mut();
+        unschedule(&mut deadlines, task.next_phase_change(), task_id);
+        task.status = TaskStatus::Disputed;
+        task.dispute = Some(Dispute {
+            opened_by: caller,
+            reason,
+            bond: DISPUTE_BOND,
+            opened_at: now,
+            jury,
+            verdicts: vec![],
+            deadline: now.saturating_add(u64::try_from(JURY_PERIOD.as_nanos()).unwrap()),
+            outcome: None,
+        });
+        schedule(&mut deadlines, task.next_phase_change(), task_id);
+        Ok(())
+    })
+}
+
+// Checks whether the caller can dispute the given task and returns the
+// principals that can serve on its jury, sorted.
+fn dispute_candidates(
+    s: &State,
+    caller: Principal,
+    now: Timestamp,
+    task_id: TaskId,
+    reason: &str,
+) -> Result<Vec<Principal>, ButlerError> {
+    let ledger = s.ledger.borrow();
+    let tasks = s.tasks.borrow();
+    let answers = s.answers.borrow();
+
+    // Precondition: caller is a principal on the ledger
+    let balance = *ledger
+        .get(&Account::from(caller))
+        .ok_or(ButlerError::NotRegistered)?;
+    let task = tasks
+        .get(&task_id)
+        .ok_or(ButlerError::TaskNotFound(task_id))?;
+    // Precondition: the caller is the submitter or one of the answerers
+    let is_answerer = task
+        .answers
+        .iter()
+        .filter_map(|answer_id| answers.get(answer_id))
+        .any(|answer| answer.submitter == caller);
+    if task.submitter != caller && !is_answerer {
+        return Err(ButlerError::NotParticipant(task_id));
+    }
+    // Precondition: the task was settled by votes and its payouts are still held
+    let dispute_deadline = match task.dispute_deadline {
+        Some(deadline) if task.status == TaskStatus::Closed && !task.payouts_released => deadline,
+        _ => return Err(ButlerError::NotDisputable(task_id)),
+    };
+    if dispute_deadline < now {
+        return Err(ButlerError::DeadlinePassed {
+            deadline: dispute_deadline,
+        });
+    }
+    if reason.len() > MAX_DISPUTE_REASON {
+        return Err(ButlerError::PayloadTooLarge {
+            max_size: MAX_DISPUTE_REASON as u64,
+            size: reason.len() as u64,
+        });
+    }
+    // Precondition: the caller can afford the bond
+    if balance < DISPUTE_BOND {
+        return Err(ButlerError::InsufficientBalance {
+            balance,
+            required: DISPUTE_BOND,
+        });
+    }
+
+    let participants = task.participants(&answers);
+    let mut candidates: Vec<Principal> = s
+        .reputation
+        .borrow()
+        .iter()
+        .filter(|(principal, reputation)| {
+            **reputation >= MIN_JUROR_REPUTATION && !participants.contains(principal)
+        })
+        .map(|(principal, _)| *principal)
+        .collect();
+    if candidates.is_empty() {
+        return Err(ButlerError::NoJurorsAvailable);
+    }
+    candidates.sort();
+    Ok(candidates)
+}
+
+// Draws up to `size` jurors from the candidates. Each draw hashes the seed
+// together with the draw's index, so the jury only depends on the seed and
+// the candidates.
+fn select_jury(mut candidates: Vec<Principal>, seed: &[u8], size: usize) -> Vec<Principal> {
+    let mut jury = vec![];
+    let mut draw: u64 = 0;
+    while jury.len() < size && !candidates.is_empty() {
+        let mut hasher = Sha256::new();
+        hasher.update(seed);
+        hasher.update(draw.to_be_bytes());
+        let hash = hasher.finalize();
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        let index = (u64::from_be_bytes(bytes) % candidates.len() as u64) as usize;
+        jury.push(candidates.swap_remove(index));
+        draw += 1;
+    }
+    jury
+}
+
+// Delivers a juror's verdict on a disputed task. The dispute is resolved as
+// soon as the whole jury has decided.
+#[update]
+fn cast_verdict(task_id: TaskId, verdict: Verdict) -> Result<(), ButlerError> {
+    cast_verdict_impl(caller(), time(), task_id, verdict)
+}
+
+fn cast_verdict_impl(
+    caller: Principal,
+    now: Timestamp,
+    task_id: TaskId,
+    verdict: Verdict,
+) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let mut tasks = s.tasks.borrow_mut();
+        let task = tasks
+            .get_mut(&task_id)
+            .ok_or(ButlerError::TaskNotFound(task_id))?;
+        let task_answers = &task.answers;
+        let dispute = match (&task.status, &mut task.dispute) {
+            (TaskStatus::Disputed, Some(dispute)) => dispute,
+            _ => return Err(ButlerError::NoOpenDispute(task_id)),
+        };
+
+        // Precondition: the caller is on the jury
+        if !dispute.jury.contains(&caller) {
+            return Err(ButlerError::NotJuror(task_id));
+        }
+        if dispute.deadline < now {
+            return Err(ButlerError::DeadlinePassed {
+                deadline: dispute.deadline,
+            });
+        }
+        // Precondition: the caller has not delivered a verdict yet
+        if dispute
+            .verdicts
+            .iter()
+            .any(|verdict| verdict.juror == caller)
+        {
+            return Err(ButlerError::VerdictAlreadyCast(task_id));
+        }
+        // Precondition: an awarded answer is an answer for the given task
+        if let Verdict::Award(answer_id) = verdict {
+            if !task_answers.contains(&answer_id) {
+                return Err(ButlerError::AnswerNotInTask { answer_id, task_id });
+            }
+        }
+
+        dispute.verdicts.push(JurorVerdict {
+            juror: caller,
+            verdict,
+        });
+        if dispute.verdicts.len() == dispute.jury.len() {
+            let deadline = dispute.deadline;
+            unschedule(&mut s.deadlines.borrow_mut(), deadline, task_id);
+            trap_on_error(conclude_dispute(s, task_id, task, now));
+        }
+        Ok(())
+    })
+}
+
+// Returns the outcome of a dispute. The reward is only awarded to an answer
+// that received more verdicts than any other answer and than the settled
+// outcome; the settled outcome stands otherwise.
+fn tally_verdicts(verdicts: &[JurorVerdict]) -> Verdict {
+    let mut counts: BTreeMap<AnswerId, usize> = BTreeMap::new();
+    let mut upheld = 0;
+    for juror_verdict in verdicts {
+        match juror_verdict.verdict {
+            Verdict::Uphold => upheld += 1,
+            Verdict::Award(answer_id) => *counts.entry(answer_id).or_default() += 1,
+        }
+    }
+    let max = counts.values().copied().max().unwrap_or(0);
+    let mut leaders = counts.iter().filter(|(_, count)| **count == max);
+    match (leaders.next(), leaders.next()) {
+        (Some((answer_id, _)), None) if max > upheld => Verdict::Award(*answer_id),
+        _ => Verdict::Uphold,
+    }
+}
+
+#[update]
+fn cancel_task(task_id: TaskId) -> Result<(), ButlerError> {
+    cancel_task_impl(caller(), time(), task_id)
+}
+
+fn cancel_task_impl(caller: Principal, now: Timestamp, task_id: TaskId) -> Result<(), ButlerError> {
+    STATE.with(|s| {
+        let mut tasks = s.tasks.borrow_mut();
+        let answers = s.answers.borrow();
+        let task = tasks
+            .get_mut(&task_id)
+            .ok_or(ButlerError::TaskNotFound(task_id))?;
+
+        // Precondition: only the submitter can cancel a task
+        if task.submitter != caller {
+            return Err(ButlerError::NotSubmitter(task_id));
+        }
+        // Precondition: the task is still open
+        if task.status != TaskStatus::Open {
+            return Err(ButlerError::TaskNotOpen(task_id));
+        }
+        if task.answer_deadline < now {
+            return Err(ButlerError::DeadlinePassed {
+                deadline: task.answer_deadline,
+            });
+        }
+
+        let mut answer_ids: Vec<&AnswerId> = task.answers.iter().collect();
+        answer_ids.sort();
+        let mut answerers = vec![];
+        for answer_id in answer_ids {
+            match answers.get(answer_id) {
+                Some(answer) => answerers.push((*answer_id, answer.submitter)),
+                // This is a case which should not occur, but let's catch it just to be sure
+                None => ic_cdk::trap(&format!(
+                    "The answer with ID {} was listed in task with ID {} even though there is no\
+                    such answer recorded.",
+                    answer_id, task_id
+                )),
+            }
+        }
+
+        // Precondition: the cancellation policy allows cancelling answered tasks
+        let compensation = if answerers.is_empty() {
+            0
+        } else {
+            match task.cancellation_policy {
+                CancellationPolicy::WithoutAnswers => {
+                    return Err(ButlerError::TaskHasAnswers(task_id));
+                }
+                CancellationPolicy::CompensateAnswerers { percent } => {
+                    (task.reward as u128 * percent as u128 / 100 / answerers.len() as u128)
+                        as Amount
+                }
+            }
+        };
+
+        // Pay the answerers their compensation and refund the rest of the
+        // escrowed reward to the sponsors. The compensation is recorded as
+        // the payouts of the task.
+        let mut ledger = s.ledger.borrow_mut();
+        let mut transactions = s.transactions.borrow_mut();
+        let mut payouts = vec![];
+        for (answer_id, answerer) in answerers.iter() {
+            trap_on_error(pay_from_escrow(
+                &mut ledger,
+                &mut transactions,
+                task_id,
+                TransactionKind::Payout,
+                *answerer,
+                compensation,
+                now,
+            ));
+            if compensation > 0 {
+                payouts.push(Payout {
+                    answer_id: *answer_id,
+                    recipient: *answerer,
+                    amount: compensation,
+                });
+            }
+        }
+        let refund = task.reward - compensation * answerers.len() as Amount;
+        for (sponsor, amount) in pro_rata(&task.sponsors, refund) {
+            trap_on_error(pay_from_escrow(
+                &mut ledger,
+                &mut transactions,
+                task_id,
+                TransactionKind::Refund,
+                sponsor,
+                amount,
+                now,
+            ));
+        }
+
+        trap_on_error(release_stakes(
+            &mut ledger,
+            &mut transactions,
+            &answers,
+            task_id,
+            task,
+            false,
+            now,
+        ));
+        // Without payouts, the voter pool is refunded to the submitter.
+        trap_on_error(distribute_voter_pool(
+            &mut ledger,
+            &mut transactions,
+            &answers,
+            task_id,
+            task,
+            now,
+        ));
+
+        unschedule(
+            &mut s.deadlines.borrow_mut(),
+            task.next_phase_change(),
+            task_id,
+        );
+        task.status = TaskStatus::Cancelled;
+        task.close_reason = Some(CloseReason::Cancelled);
+        task.payouts = payouts;
+        task.payouts_released = true;
+        Ok(())
+    })
+}
+
+// Extends the answer deadline of an open task by `extra` nanoseconds and
+// returns it. The vote deadline moves by the same amount, so the review phase
+// keeps its length. The task cannot stay open for longer than `MAX_DURATION`
+// after its creation.
+#[update]
+fn extend_deadline(task_id: TaskId, extra: Duration) -> Result<Timestamp, ButlerError> {
+    extend_deadline_impl(caller(), time(), task_id, extra)
+}
+
+fn extend_deadline_impl(
+    caller: Principal,
+    now: Timestamp,
+    task_id: TaskId,
+    extra: Duration,
+) -> Result<Timestamp, ButlerError> {
+    STATE.with(|s| {
+        let mut tasks = s.tasks.borrow_mut();
+        let task = tasks
+            .get_mut(&task_id)
+            .ok_or(ButlerError::TaskNotFound(task_id))?;
+
+        // Precondition: only the submitter can extend the deadline
+        if task.submitter != caller {
+            return Err(ButlerError::NotSubmitter(task_id));
+        }
+        // Precondition: the task is still open
+        if task.status != TaskStatus::Open {
+            return Err(ButlerError::TaskNotOpen(task_id));
+        }
+        if task.answer_deadline < now {
+            return Err(ButlerError::DeadlinePassed {
+                deadline: task.answer_deadline,
+            });
+        }
+
+        let max_deadline = task.created_at + u64::try_from(MAX_DURATION.as_nanos()).unwrap();
+        let deadline = task.answer_deadline.saturating_add(extra);
+        if deadline > max_deadline {
+            return Err(ButlerError::ExtensionTooLong { max_deadline });
+        }
+        let mut deadlines = s.deadlines.borrow_mut();
+        unschedule(&mut deadlines, task.next_phase_change(), task_id);
+        task.vote_deadline = task
+            .vote_deadline
+            .saturating_add(deadline - task.answer_deadline);
+        task.answer_deadline = deadline;
+        schedule(&mut deadlines, task.next_phase_change(), task_id);
+        Ok(deadline)
+    })
+}
+
+// Adds `amount` tokens of the caller to the reward of an open task. Anyone
+// registered can co-sponsor a task. If nobody votes on the task, the reward
+// is refunded to the sponsors in proportion to their contributions.
+#[update]
+fn add_reward(task_id: TaskId, amount: Amount) -> Result<Amount, ButlerError> {
+    add_reward_impl(caller(), time(), task_id, amount)
+}
+
+fn add_reward_impl(
+    caller: Principal,
+    now: Timestamp,
+    task_id: TaskId,
+    amount: Amount,
+) -> Result<Amount, ButlerError> {
+    STATE.with(|s| {
+        let mut tasks = s.tasks.borrow_mut();
+        let task = tasks
+            .get_mut(&task_id)
+            .ok_or(ButlerError::TaskNotFound(task_id))?;
+
+        if amount == 0 {
+            return Err(ButlerError::InvalidAmount);
+        }
+        // Precondition: the task is still open
+        if task.status != TaskStatus::Open {
+            return Err(ButlerError::TaskNotOpen(task_id));
+        }
+        if task.answer_deadline < now {
+            return Err(ButlerError::DeadlinePassed {
+                deadline: task.answer_deadline,
+            });
+        }
+
+        let mut ledger = s.ledger.borrow_mut();
+        // Precondition: caller is a principal on the ledger
+        if !ledger.contains_key(&Account::from(caller)) {
+            return Err(ButlerError::NotRegistered);
+        }
+        let reward = task
+            .reward
+            .checked_add(amount)
+            .ok_or_else(|| ButlerError::BalanceOverflow(escrow_account(task_id)))?;
+
+        let mut escrow = Transaction::new(
+            TransactionKind::Escrow,
+            Some(Account::from(caller)),
+            Some(escrow_account(task_id)),
+            amount,
+            now,
+        );
+        escrow.task_id = Some(task_id);
+        execute(&mut ledger, &mut s.transactions.borrow_mut(), escrow)?;
+
+        task.reward = reward;
+        match task.sponsors.iter_mut().find(|s| s.sponsor == caller) {
+            Some(sponsor) => sponsor.amount += amount,
+            None => task.sponsors.push(Sponsor {
+                sponsor: caller,
+                amount,
+            }),
+        }
+        Ok(reward)
+    })
+}
+
+// The state kept in stable memory across upgrades. Every change of the
+// layout adds a variant, so that the state of older versions can still be
+// read.
+#[derive(CandidType, Deserialize)]
+enum StableState {
+    #[serde(rename = "v1")]
+    V1(StableStateV1),
+}
+
+#[derive(CandidType, Deserialize)]
+struct StableStateV1 {
+    next_task_id: TaskId,
+    tasks: BTreeMap<TaskId, TaskInternal>,
+    answers: HashMap<AnswerId, Answer>,
+    next_answer_id: AnswerId,
+    ledger: HashMap<Account, Amount>,
+    registered: HashSet<Principal>,
+    transactions: Vec<Transaction>,
+    reputation: HashMap<Principal, i64>,
+    skills: HashMap<Principal, HashMap<Skill, i64>>,
+}
+
+// The first release saved its state as a plain tuple without a version.
+type LegacyStableState = (
+    TaskId,
+    HashMap<TaskId, LegacyTaskInternal>,
+    HashMap<AnswerId, LegacyAnswer>,
+    AnswerId,
+    HashMap<Principal, Amount>,
+);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct LegacyTaskInternal {
+    submitter: Principal,
+    task_type: TaskType,
+    payload: TaskPayload,
+    deadline: Timestamp,
+    reward: Amount,
+    answers: HashSet<AnswerId>,
+    status: TaskStatus,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct LegacyAnswer {
+    submitter: Principal,
+    submission_time: Timestamp,
+    content: Content,
+    votes: Vec<LegacyVote>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct LegacyVote {
+    voter: Principal,
+    choice: Choice,
+}
+
+// Converts the state of the first release. Its tasks were voted on while
+// open and settled at their deadline, which is kept as both the answer and
+// the vote deadline. The rewards of open tasks were deducted from their
+// submitters without being held anywhere, so they are moved into escrow and
+// the deduction is recorded as an escrow transaction.
+fn migrate_legacy_state(legacy: LegacyStableState) -> StableStateV1 {
+    let (next_task_id, legacy_tasks, legacy_answers, next_answer_id, legacy_ledger) = legacy;
+    // Only registered principals held tokens in the first release.
+    let registered = legacy_ledger.keys().copied().collect();
+    let mut ledger: HashMap<Account, Amount> = legacy_ledger
+        .into_iter()
+        .map(|(principal, amount)| (Account::from(principal), amount))
+        .collect();
+    let mut transactions = vec![];
+    let mut tasks = BTreeMap::new();
+    // Tasks are migrated in order so that escrow transactions are numbered
+    // by task ID.
+    let legacy_tasks: BTreeMap<TaskId, LegacyTaskInternal> = legacy_tasks.into_iter().collect();
+    for (task_id, task) in legacy_tasks {
+        // The creation time was not recorded. Deadlines were at most
+        // `MAX_DURATION` after creation, so the earliest possible creation
+        // time keeps extensions within that limit.
+        let created_at = task
+            .deadline
+            .saturating_sub(u64::try_from(MAX_DURATION.as_nanos()).unwrap());
+        let open = task.status == TaskStatus::Open;
+        if open && task.reward > 0 {
+            ledger.insert(escrow_account(task_id), task.reward);
+            let mut escrow = Transaction::new(
+                TransactionKind::Escrow,
+                Some(Account::from(task.submitter)),
+                Some(escrow_account(task_id)),
+                task.reward,
+                created_at,
+            );
+            escrow.index = transactions.len() as BlockIndex;
+            escrow.task_id = Some(task_id);
+            transactions.push(escrow);
+        }
+        let language = match task.task_type {
+            TaskType::TranslateText => Decode!(&task.payload, TranslateTextInput)
+                .ok()
+                .map(|input| input.language),
+            TaskType::EditImage => None,
+        };
+        tasks.insert(
+            task_id,
+            TaskInternal {
+                submitter: task.submitter,
+                task_type: task.task_type,
+                payload: task.payload,
+                answer_deadline: task.deadline,
+                vote_deadline: task.deadline,
+                reward: task.reward,
+                answers: task.answers,
+                status: task.status,
+                cancellation_policy: CancellationPolicy::WithoutAnswers,
+                created_at,
+                sponsors: vec![Sponsor {
+                    sponsor: task.submitter,
+                    amount: task.reward,
+                }],
+                payout_scheme: PayoutScheme::WinnerTakesAll,
+                payouts: vec![],
+                vote_weighting: VoteWeighting::Equal,
+                language,
+                min_skill_score: None,
+                commit_reveal: None,
+                answer_visibility: AnswerVisibility::Public,
+                settlement_mode: SettlementMode::Vote,
+                dispute_deadline: None,
+                // Closed tasks were paid out when they were settled.
+                payouts_released: !open,
+                dispute: None,
+                voter_pool: None,
+                voter_rewards: vec![],
+                quorum: None,
+                review_extended: false,
+                close_reason: None,
+                conflict_of_interest: ConflictOfInterest::default(),
+            },
+        );
+    }
+    let answers = legacy_answers
+        .into_iter()
+        .map(|(answer_id, answer)| {
+            let votes = answer
+                .votes
+                .into_iter()
+                .map(|vote| Vote {
+                    voter: vote.voter,
+                    choice: vote.choice,
+                    weight: 1,
+                    stake: 0,
+                })
+                .collect();
+            let answer = Answer {
+                submitter: answer.submitter,
+                submission_time: answer.submission_time,
+                content: answer.content,
+                votes,
+                commitments: vec![],
+                vote_changes: vec![],
+                revisions: vec![],
+            };
+            (answer_id, answer)
+        })
+        .collect();
+    StableStateV1 {
+        next_task_id,
+        tasks,
+        answers,
+        next_answer_id,
+        ledger,
+        registered,
+        transactions,
+        reputation: HashMap::new(),
+        skills: HashMap::new(),
+    }
+}
+
+#[pre_upgrade]
+fn save_data() {
+    STATE.with(|s| {
+        let state = StableState::V1(StableStateV1 {
+            next_task_id: s.next_task_id.take(),
+            tasks: s.tasks.take(),
+            answers: s.answers.take(),
+            next_answer_id: s.next_answer_id.take(),
+            ledger: s.ledger.take(),
+            registered: s.registered.take(),
+            transactions: s.transactions.take().into_inner(),
+            reputation: s.reputation.take(),
+            skills: s.skills.take(),
+        });
+        if let Err(err) = stable_save((state,)) {
+            ic_cdk::trap(&format!("Could not store data to stable memory: {}", err));
+        }
+    });
+}
+
+#[post_upgrade]
+fn retrieve_data() {
+    let state = match stable_restore::<(StableState,)>() {
+        Ok((StableState::V1(state),)) => state,
+        Err(err) => match stable_restore::<LegacyStableState>() {
+            Ok(legacy) => migrate_legacy_state(legacy),
+            Err(_) => {
+                ic_cdk::trap(&format!("Could not read data from stable memory: {}", err));
+            }
+        },
+    };
+    STATE.with(|s| restore_state(s, state));
+}
+
+// Replaces the state with the one read from stable memory and rebuilds the
+// indexes that are derived from it.
+fn restore_state(s: &State, state: StableStateV1) {
+    s.next_task_id.replace(state.next_task_id);
+    s.tasks.replace(state.tasks);
+    s.answers.replace(state.answers);
+    s.next_answer_id.replace(state.next_answer_id);
+    s.ledger.replace(state.ledger);
+    s.registered.replace(state.registered);
+    s.transactions
+        .replace(TransactionLog::new(state.transactions));
+    s.reputation.replace(state.reputation);
+    s.skills.replace(state.skills);
+
+    let mut deadlines = s.deadlines.borrow_mut();
+    let mut answer_tasks = s.answer_tasks.borrow_mut();
+    let mut submitted_tasks = s.submitted_tasks.borrow_mut();
+    for (task_id, task) in s.tasks.borrow().iter() {
+        if task.is_pending() {
+            schedule(&mut deadlines, task.next_phase_change(), *task_id);
+        }
+        for answer_id in task.answers.iter() {
+            answer_tasks.insert(*answer_id, *task_id);
+        }
+        index_insert(&mut submitted_tasks, task.submitter, *task_id);
+    }
+    let mut authored_answers = s.authored_answers.borrow_mut();
+    let mut voted_answers = s.voted_answers.borrow_mut();
+    for (answer_id, answer) in s.answers.borrow().iter() {
+        index_insert(&mut authored_answers, answer.submitter, *answer_id);
+        let voters = answer.votes.iter().map(|vote| vote.voter);
+        let committers = answer.commitments.iter().map(|commitment| commitment.voter);
+        for voter in voters.chain(committers) {
+            index_insert(&mut voted_answers, voter, *answer_id);
+        }
+    }
+}
+
+#[export_name = "canister_heartbeat"]
+fn hearbeat() {
+    let now = time();
+    STATE.with(|s| {
+        process_due_tasks(s, now, || {
+            instruction_counter() < HEARTBEAT_INSTRUCTION_BUDGET
+        })
+    });
+}
+
+// Moves due tasks into their next phase, earliest deadline first, for as long
+// as `within_budget` allows. A task that cannot be moved on, e.g. because its
+// escrow is out of sync, is left as it was and logged. It is not rescheduled,
+// so it does not hold up the tasks after it.
+fn process_due_tasks(s: &State, now: Timestamp, mut within_budget: impl FnMut() -> bool) {
+    while within_budget() {
+        let task_id = match pop_due_tasks(&mut s.deadlines.borrow_mut(), now, 1).pop() {
+            Some(task_id) => task_id,
+            None => break,
+        };
+        let task = match s.tasks.borrow().get(&task_id) {
+            Some(task) => task.clone(),
+            None => continue,
+        };
+        let transaction_count = s.transactions.borrow().len();
+        let result = match task.status {
+            TaskStatus::Open => {
+                start_review(s, task_id, now);
+                Ok(())
+            }
+            TaskStatus::InReview => settle_task(s, task_id, now),
+            TaskStatus::Closed => release_payouts(s, task_id, now),
+            TaskStatus::Disputed => resolve_dispute(s, task_id, now),
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            revert_transactions(
+                &mut s.ledger.borrow_mut(),
+                &mut s.transactions.borrow_mut(),
+                transaction_count,
+            );
+            s.tasks.borrow_mut().insert(task_id, task);
+            log(&format!("Could not process task {}: {}", task_id, err));
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn log(message: &str) {
+    ic_cdk::print(message);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn log(message: &str) {
+    eprintln!("{}", message);
+}
+
+// The number of instructions executed so far in the current message. The
+// performance counter is not exposed by ic-cdk yet, so it is imported from
+// the system API directly.
+#[cfg(target_arch = "wasm32")]
+fn instruction_counter() -> u64 {
+    #[link(wasm_import_module = "ic0")]
+    extern "C" {
+        fn performance_counter(counter_type: u32) -> u64;
+    }
+    // Safe because reading counter 0, the instruction counter, has no side
+    // effects.
+    unsafe { performance_counter(0) }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn instruction_counter() -> u64 {
+    0
+}
+
+// Removes and returns up to `limit` tasks whose deadline is before `now`,
+// earliest deadline first. The remaining expired tasks are left in the index
+// for the following rounds.
+fn pop_due_tasks(
+    deadlines: &mut BTreeMap<Timestamp, Vec<TaskId>>,
+    now: Timestamp,
+    limit: usize,
+) -> Vec<TaskId> {
+    let mut due = vec![];
+    while due.len() < limit {
+        let deadline = match deadlines.keys().next() {
+            Some(deadline) if *deadline < now => *deadline,
+            _ => break,
+        };
+        // Safe because the deadline was just read from the index.
+        let task_ids = deadlines.get_mut(&deadline).unwrap();
+        while due.len() < limit {
+            match task_ids.pop() {
+                Some(task_id) => due.push(task_id),
+                None => break,
+            }
+        }
+        if task_ids.is_empty() {
+            deadlines.remove(&deadline);
+        }
+    }
+    due
+}
+
+fn schedule(
+    deadlines: &mut BTreeMap<Timestamp, Vec<TaskId>>,
+    deadline: Timestamp,
+    task_id: TaskId,
+) {
+    deadlines.entry(deadline).or_default().push(task_id);
+}
+
+fn unschedule(
+    deadlines: &mut BTreeMap<Timestamp, Vec<TaskId>>,
+    deadline: Timestamp,
+    task_id: TaskId,
+) {
+    if let Some(task_ids) = deadlines.get_mut(&deadline) {
+        task_ids.retain(|id| *id != task_id);
+        if task_ids.is_empty() {
+            deadlines.remove(&deadline);
+        }
+    }
+}
+
+// Moves a task whose answer deadline has passed into its review phase.
+fn start_review(s: &State, task_id: TaskId, now: Timestamp) {
+    let mut tasks = s.tasks.borrow_mut();
+    if let Some(task) = tasks.get_mut(&task_id) {
+        if task.status == TaskStatus::Open && task.answer_deadline < now {
+            task.status = TaskStatus::InReview;
+            schedule(
+                &mut s.deadlines.borrow_mut(),
+                task.next_phase_change(),
+                task_id,
+            );
+        }
+    }
+}
+
+// Closes a task whose review is over and determines its payouts according
+// to its payout scheme. The payouts and the voters' stakes are held in
+// escrow until the dispute deadline. Tasks below their quorum get their
+// review extended or their reward refunded instead.
+fn settle_task(s: &State, task_id: TaskId, now: Timestamp) -> Result<(), String> {
+    let mut tasks = s.tasks.borrow_mut();
+    let task = match tasks.get_mut(&task_id) {
+        Some(task) if task.status == TaskStatus::InReview && task.settlement_time() < now => task,
+        _ => return Ok(()),
+    };
+
+    let all_answers = s.answers.borrow();
+    let answers = task_answers(&all_answers, task_id, task);
+    if let Some(quorum) = &task.quorum {
+        if !quorum.is_met(&answers) {
+            if quorum.fallback == QuorumFallback::ExtendReview && !task.review_extended {
+                let review_period = task.vote_deadline - task.answer_deadline;
+                task.vote_deadline = task.vote_deadline.saturating_add(review_period);
+                task.review_extended = true;
+                schedule(
+                    &mut s.deadlines.borrow_mut(),
+                    task.next_phase_change(),
+                    task_id,
+                );
+                return Ok(());
+            }
+            // Without payouts, the whole reward and the voter pool are
+            // refunded. There is nothing to dispute.
+            task.status = TaskStatus::Closed;
+            task.close_reason = Some(CloseReason::QuorumNotReached);
+            let mut ledger = s.ledger.borrow_mut();
+            let mut transactions = s.transactions.borrow_mut();
+            pay_out(&mut ledger, &mut transactions, task_id, task, now)?;
+            release_stakes(
+                &mut ledger,
+                &mut transactions,
+                &all_answers,
+                task_id,
+                task,
+                true,
+                now,
+            )?;
+            distribute_voter_pool(
+                &mut ledger,
+                &mut transactions,
+                &all_answers,
+                task_id,
+                task,
+                now,
+            )?;
+            task.payouts_released = true;
+            return Ok(());
+        }
+    }
+
+    task.status = TaskStatus::Closed;
+    let ranked = rank_answers(answers);
+    task.close_reason = Some(if ranked.is_empty() {
+        CloseReason::NoVotes
+    } else {
+        CloseReason::Votes
+    });
+    task.payouts = compute_payouts(&task.payout_scheme, task.reward, &ranked);
+
+    let dispute_deadline = now.saturating_add(u64::try_from(DISPUTE_WINDOW.as_nanos()).unwrap());
+    task.dispute_deadline = Some(dispute_deadline);
+    schedule(&mut s.deadlines.borrow_mut(), dispute_deadline, task_id);
+    Ok(())
+}
+
+// Returns the answers of a task. Failing to find one means the state is
+// inconsistent, so this traps.
+fn task_answers<'a>(
+    all_answers: &'a HashMap<AnswerId, Answer>,
+    task_id: TaskId,
+    task: &TaskInternal,
+) -> Vec<(AnswerId, &'a Answer)> {
+    let mut task_answers = vec![];
+    for answer_id in task.answers.iter() {
+        match all_answers.get(answer_id) {
+            // This is a case which should not occur, but let's catch it just to be sure
+            None => {
+                ic_cdk::trap(&format!(
+                    "The answer with ID {} was listed in task with ID {} even though there is no\
+                such answer recorded.",
+                    answer_id, task_id
+                ));
+            }
+            Some(answer) => {
+                task_answers.push((*answer_id, answer));
+            }
+        }
+    }
+    task_answers
+}
+
+// Releases the payouts of a settled task once its dispute deadline has
+// passed without a dispute.
+fn release_payouts(s: &State, task_id: TaskId, now: Timestamp) -> Result<(), String> {
+    let mut tasks = s.tasks.borrow_mut();
+    let task = match tasks.get_mut(&task_id) {
+        Some(task)
+            if task.status == TaskStatus::Closed
+                && !task.payouts_released
+                && matches!(task.dispute_deadline, Some(deadline) if deadline < now) =>
+        {
+            task
+        }
+        _ => return Ok(()),
+    };
+    finalize_task(s, task_id, task, now)
+}
+
+// Resolves a dispute whose jury deadline has passed with the verdicts
+// delivered so far.
+fn resolve_dispute(s: &State, task_id: TaskId, now: Timestamp) -> Result<(), String> {
+    let mut tasks = s.tasks.borrow_mut();
+    let task = match tasks.get_mut(&task_id) {
+        Some(task) if task.status == TaskStatus::Disputed => task,
+        _ => return Ok(()),
+    };
+    match &task.dispute {
+        Some(dispute) if dispute.deadline < now => {}
+        _ => return Ok(()),
+    }
+    conclude_dispute(s, task_id, task, now)
+}
+
+// Applies the outcome of a dispute and releases the task's payouts. If the
+// settled outcome stands, the bond is shared among the jurors who delivered
+// a verdict. Otherwise the whole reward goes to the awarded answer and the
+// bond is refunded, as it is when no juror delivered a verdict.
+fn conclude_dispute(
+    s: &State,
+    task_id: TaskId,
+    task: &mut TaskInternal,
+    now: Timestamp,
+) -> Result<(), String> {
+    let dispute = task
+        .dispute
+        .as_mut()
+        .ok_or_else(|| format!("Inconsistent state. Task {} has no dispute", task_id))?;
+    let outcome = tally_verdicts(&dispute.verdicts);
+    dispute.outcome = Some(outcome.clone());
+
+    let mut ledger = s.ledger.borrow_mut();
+    let mut transactions = s.transactions.borrow_mut();
+    if outcome == Verdict::Uphold && !dispute.verdicts.is_empty() {
+        let share = dispute.bond / dispute.verdicts.len() as Amount;
+        let remainder = dispute.bond % dispute.verdicts.len() as Amount;
+        for (i, juror_verdict) in dispute.verdicts.iter().enumerate() {
+            let amount = if i == 0 { share + remainder } else { share };
+            pay_from_escrow(
+                &mut ledger,
+                &mut transactions,
+                task_id,
+                TransactionKind::JurorReward,
+                juror_verdict.juror,
+                amount,
+                now,
+            )?;
+        }
+    } else {
+        pay_from_escrow(
+            &mut ledger,
+            &mut transactions,
+            task_id,
+            TransactionKind::BondRefund,
+            dispute.opened_by,
+            dispute.bond,
+            now,
+        )?;
+    }
+    drop(ledger);
+    drop(transactions);
+
+    if let Verdict::Award(answer_id) = outcome {
+        let answers = s.answers.borrow();
+        let answer = answers
+            .get(&answer_id)
+            .ok_or_else(|| format!("Inconsistent state. AnswerId {} cannot be found", answer_id))?;
+        task.payouts = vec![Payout {
+            answer_id,
+            recipient: answer.submitter,
+            amount: task.reward,
+        }];
+    }
+    task.status = TaskStatus::Closed;
+    finalize_task(s, task_id, task, now)
+}
+
+// Pays out the final payouts of a task settled by votes, releases the
+// voters' stakes, distributes the voter pool and updates the reputation and
+// skills of the participants.
+fn finalize_task(
+    s: &State,
+    task_id: TaskId,
+    task: &mut TaskInternal,
+    now: Timestamp,
+) -> Result<(), String> {
+    let all_answers = s.answers.borrow();
+    let mut ledger = s.ledger.borrow_mut();
+    let mut transactions = s.transactions.borrow_mut();
+    pay_out(&mut ledger, &mut transactions, task_id, task, now)?;
+    // Sealed votes that were not revealed are dropped and their deposits burned.
+    release_stakes(
+        &mut ledger,
+        &mut transactions,
+        &all_answers,
+        task_id,
+        task,
+        true,
+        now,
+    )?;
+    distribute_voter_pool(
+        &mut ledger,
+        &mut transactions,
+        &all_answers,
+        task_id,
+        task,
+        now,
+    )?;
+    task.payouts_released = true;
+
+    let ranked = rank_answers(task_answers(&all_answers, task_id, task));
+    // The winning answer is the top-ranked one, unless a jury awarded the
+    // reward to another answer.
+    let winner = task
+        .payouts
+        .first()
+        .map(|payout| payout.answer_id)
+        .or_else(|| ranked.first().map(|answer| answer.answer_id));
+    let winning_votes = match winner {
+        // Safe because payouts and ranked answers are answers of the task.
+        Some(winner) => &all_answers.get(&winner).unwrap().votes[..],
+        None => &[],
+    };
+    update_reputation(
+        &mut s.reputation.borrow_mut(),
+        &ranked,
+        &task.payouts,
+        winning_votes,
+    );
+    update_skills(
+        &mut s.skills.borrow_mut(),
+        &task.skills(),
+        &ranked,
+        &task.payouts,
+    );
+    Ok(())
+}
+
+// Pays the voter pool of a closed or cancelled task, together with the
+// slashed stakes, to the voters who voted yes on the winning answer. If
+// there is no such voter, it is refunded to the submitter.
+fn distribute_voter_pool(
+    ledger: &mut HashMap<Account, Amount>,
+    transactions: &mut TransactionLog,
+    answers: &HashMap<AnswerId, Answer>,
+    task_id: TaskId,
+    task: &mut TaskInternal,
+    now: Timestamp,
+) -> Result<(), String> {
+    let pool_amount = match &task.voter_pool {
+        Some(voter_pool) => voter_pool.amount,
+        None => return Ok(()),
+    };
+    let winner = task.winning_answer();
+    let winning_votes = match winner {
+        Some(answer_id) => match answers.get(&answer_id) {
+            Some(answer) => &answer.votes[..],
+            None => {
+                return Err(format!(
+                    "Inconsistent state. AnswerId {} cannot be found",
+                    answer_id
+                ))
+            }
+        },
+        None => &[],
+    };
+    let slashed: Amount = winner.map_or(0, |answer_id| {
+        winning_votes
+            .iter()
+            .map(|vote| task.slashed_stake(answer_id, vote))
+            .sum()
+    });
+
+    task.voter_rewards = compute_voter_rewards(pool_amount + slashed, winning_votes);
+    for reward in task.voter_rewards.iter() {
+        pay_from_escrow(
+            ledger,
+            transactions,
+            task_id,
+            TransactionKind::VoterReward,
+            reward.voter,
+            reward.amount,
+            now,
+        )?;
+    }
+    if task.voter_rewards.is_empty() {
+        pay_from_escrow(
+            ledger,
+            transactions,
+            task_id,
+            TransactionKind::Refund,
+            task.submitter,
+            pool_amount + slashed,
+            now,
+        )?;
+    }
+    Ok(())
+}
+
+// Pays the payouts of a closed task out of its escrow and refunds the rest
+// of the reward to the sponsors.
+fn pay_out(
+    ledger: &mut HashMap<Account, Amount>,
+    transactions: &mut TransactionLog,
+    task_id: TaskId,
+    task: &TaskInternal,
+    now: Timestamp,
+) -> Result<(), String> {
+    for payout in task.payouts.iter() {
+        pay_from_escrow(
+            ledger,
+            transactions,
+            task_id,
+            TransactionKind::Payout,
+            payout.recipient,
+            payout.amount,
+            now,
+        )?;
+    }
+
+    // Whatever is not paid out, e.g. the whole reward if nobody voted, is
+    // returned to the sponsors.
+    let paid: Amount = task.payouts.iter().map(|payout| payout.amount).sum();
+    for (sponsor, amount) in pro_rata(&task.sponsors, task.reward - paid) {
+        pay_from_escrow(
+            ledger,
+            transactions,
+            task_id,
+            TransactionKind::Refund,
+            sponsor,
+            amount,
+            now,
+        )?;
+    }
+    Ok(())
+}
+
+// Ranks the answers that received votes by their net votes. Ties go to the
+// earliest submission.
+fn rank_answers(mut answers: Vec<(AnswerId, &Answer)>) -> Vec<RankedAnswer> {
+    answers.sort_by_key(|(answer_id, answer)| (answer.submission_time, *answer_id));
+    let mut ranked: Vec<RankedAnswer> = answers
+        .into_iter()
+        .filter(|(_, answer)| !answer.votes.is_empty())
+        .map(|(answer_id, answer)| RankedAnswer {
+            answer_id,
+            submitter: answer.submitter,
+            net_votes: answer
+                .votes
+                .iter()
+                .map(|v| match v.choice {
+                    Choice::Yes => v.weight as i128,
+                    Choice::No => -(v.weight as i128),
+                })
+                .sum::<i128>(),
+        })
+        .collect();
+    // The sort is stable, so earlier submissions stay ahead on ties.
+    ranked.sort_by_key(|answer| std::cmp::Reverse(answer.net_votes));
+    ranked
+}
+
+// Updates the reputation of the participants of a settled task: authors of
+// paid answers gain, authors of answers with negative net votes lose and
+// voters who voted yes on the top-ranked answer gain.
+fn update_reputation(
+    reputation: &mut HashMap<Principal, i64>,
+    ranked: &[RankedAnswer],
+    payouts: &[Payout],
+    winning_votes: &[Vote],
+) {
+    let mut adjust = |principal: Principal, delta: i64| {
+        let entry = reputation.entry(principal).or_insert(0);
+        *entry = entry.saturating_add(delta);
+    };
+    for payout in payouts {
+        adjust(payout.recipient, REPUTATION_FOR_PAYOUT);
+    }
+    for answer in ranked.iter().filter(|answer| answer.net_votes < 0) {
+        adjust(answer.submitter, -REPUTATION_FOR_REJECTED_ANSWER);
+    }
+    for vote in winning_votes
+        .iter()
+        .filter(|vote| vote.choice == Choice::Yes)
+    {
+        adjust(vote.voter, REPUTATION_FOR_WINNING_VOTE);
+    }
+}
+
+// Updates the skill scores of the answer authors of a settled task in every
+// skill exercised by the task, by the same amounts as their reputation.
+fn update_skills(
+    skills: &mut HashMap<Principal, HashMap<Skill, i64>>,
+    task_skills: &[Skill],
+    ranked: &[RankedAnswer],
+    payouts: &[Payout],
+) {
+    let mut adjust = |principal: Principal, delta: i64| {
+        let scores = skills.entry(principal).or_default();
+        for skill in task_skills {
+            let score = scores.entry(skill.clone()).or_insert(0);
+            *score = score.saturating_add(delta);
+        }
+    };
+    for payout in payouts {
+        adjust(payout.recipient, REPUTATION_FOR_PAYOUT);
+    }
+    for answer in ranked.iter().filter(|answer| answer.net_votes < 0) {
+        adjust(answer.submitter, -REPUTATION_FOR_REJECTED_ANSWER);
+    }
+}
+
+// Splits a voter pool among the yes votes on the winning answer, in
+// proportion to their weight. The rounding remainder goes to the first of them.
+fn compute_voter_rewards(pool: Amount, votes: &[Vote]) -> Vec<VoterReward> {
+    let yes_votes: Vec<&Vote> = votes
+        .iter()
+        .filter(|vote| vote.choice == Choice::Yes)
+        .collect();
+    let total_weight: u128 = yes_votes.iter().map(|vote| vote.weight as u128).sum();
+    if total_weight == 0 {
+        return vec![];
+    }
+    let mut rewards: Vec<VoterReward> = yes_votes
+        .iter()
+        .map(|vote| VoterReward {
+            voter: vote.voter,
+            amount: (pool as u128 * vote.weight as u128 / total_weight) as Amount,
+        })
+        .collect();
+    let paid: Amount = rewards.iter().map(|reward| reward.amount).sum();
+    rewards[0].amount += pool - paid;
+    rewards.retain(|reward| reward.amount > 0);
+    rewards
+}
+
+// Splits `reward` among the ranked answers. The rounding remainder goes to
+// the top-ranked recipient, while shares without a recipient are not paid out.
+fn compute_payouts(scheme: &PayoutScheme, reward: Amount, ranked: &[RankedAnswer]) -> Vec<Payout> {
+    let weights: Vec<u128> = match scheme {
+        PayoutScheme::WinnerTakesAll => ranked.iter().take(1).map(|_| 100).collect(),
+        PayoutScheme::TopN(percentages) => ranked
+            .iter()
+            .zip(percentages.iter())
+            .map(|(_, percentage)| *percentage as u128)
+            .collect(),
+        PayoutScheme::Proportional => ranked
+            .iter()
+            .take_while(|answer| answer.net_votes > 0)
+            .map(|answer| answer.net_votes as u128)
+            .collect(),
+    };
+    let total_weight: u128 = weights.iter().sum();
+    if total_weight == 0 {
+        return vec![];
+    }
+    // The part of the reward that has recipients.
+    let distributed = match scheme {
+        PayoutScheme::TopN(_) => reward as u128 * total_weight / 100,
+        _ => reward as u128,
+    };
+
+    let mut payouts: Vec<Payout> = ranked
+        .iter()
+        .zip(weights.iter())
+        .map(|(answer, weight)| Payout {
+            answer_id: answer.answer_id,
+            recipient: answer.submitter,
+            amount: (distributed * weight / total_weight) as Amount,
+        })
+        .collect();
+    let paid: Amount = payouts.iter().map(|payout| payout.amount).sum();
+    payouts[0].amount += distributed as Amount - paid;
+    payouts.retain(|payout| payout.amount > 0);
+    payouts
+}
+
+fn main() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::export::candid::Encode;
+
+    fn principals() -> (Principal, Principal, Principal) {
+        let principal = |text| Principal::from_text(text).unwrap();
+        (
+            principal("ollzc-44u63-k7twc-5fffp-f5vr7-ptyb5-v6esl-cswqt-f4u4t-qfxbj-6ae"),
+            principal("etn6u-fsyqb-qmjjs-murc2-w4qtf-4tota-tp2vi-y5jau-kavt5-zi6dj-lae"),
+            principal("lv3pe-37kt2-3kbhe-r2oyg-ppn4z-mftmh-j242j-gu7ps-hvun5-3ioxb-3qe"),
+        )
+    }
+
+    // An open translation task with a reward of 50, which takes answers
+    // until 200 and votes until 1000.
+    fn task(submitter: Principal, answers: &[AnswerId]) -> TaskInternal {
+        TaskInternal {
+            submitter,
+            task_type: TaskType::TranslateText,
+            payload: ByteBuf::from(vec![]),
+            answer_deadline: 200,
+            vote_deadline: 1000,
+            reward: 50,
+            answers: answers.iter().copied().collect(),
+            status: TaskStatus::Open,
+            cancellation_policy: CancellationPolicy::WithoutAnswers,
+            created_at: 0,
+            sponsors: vec![],
+            payout_scheme: PayoutScheme::WinnerTakesAll,
+            payouts: vec![],
+            vote_weighting: VoteWeighting::Equal,
+            language: None,
+            min_skill_score: None,
+            commit_reveal: None,
+            answer_visibility: AnswerVisibility::Public,
+            settlement_mode: SettlementMode::Vote,
+            dispute_deadline: None,
+            payouts_released: false,
+            dispute: None,
+            voter_pool: None,
+            voter_rewards: vec![],
+            quorum: None,
+            review_extended: false,
+            close_reason: None,
+            conflict_of_interest: ConflictOfInterest::default(),
+        }
+    }
+
+    // An answer without votes, submitted at 100.
+    fn answer(submitter: Principal) -> Answer {
+        Answer {
+            submitter,
+            submission_time: 100,
+            content: ByteBuf::from(vec![1]),
+            votes: vec![],
+            commitments: vec![],
+            vote_changes: vec![],
+            revisions: vec![],
+        }
+    }
+
+    // The kind, source, destination and amount of each transaction since
+    // the given index.
+    fn movements(since: usize) -> Vec<(TransactionKind, Option<Account>, Option<Account>, Amount)> {
+        super::STATE.with(|s| {
+            s.transactions.borrow()[since..]
+                .iter()
+                .map(|t| (t.kind.clone(), t.from.clone(), t.to.clone(), t.amount))
+                .collect()
+        })
+    }
+
+    // The balance of an account, which is 0 if it is not on the ledger.
+    fn balance(account: &Account) -> Amount {
+        super::STATE.with(|s| s.ledger.borrow().get(account).copied().unwrap_or(0))
+    }
+
+    #[test]
+    fn test_get_task() {
+        let principal1 =
+            Principal::from_text("ollzc-44u63-k7twc-5fffp-f5vr7-ptyb5-v6esl-cswqt-f4u4t-qfxbj-6ae")
+                .unwrap();
+        let principal2 =
+            Principal::from_text("etn6u-fsyqb-qmjjs-murc2-w4qtf-4tota-tp2vi-y5jau-kavt5-zi6dj-lae")
+                .unwrap();
+        let principal3 =
+            Principal::from_text("lv3pe-37kt2-3kbhe-r2oyg-ppn4z-mftmh-j242j-gu7ps-hvun5-3ioxb-3qe")
+                .unwrap();
+
+        let bytes: [u8; 7] = [65, 66, 67, 68, 69, 70, 71];
+        let v = vec![
+            Vote {
+                voter: Principal::anonymous(),
+                choice: Choice::Yes,
+                weight: 1,
+                stake: 0,
+            },
+            Vote {
+                voter: Principal::anonymous(),
+                choice: Choice::Yes,
+                weight: 1,
+                stake: 0,
+            },
+        ];
+
+        let mut answers_map = HashMap::new();
+        let id = 1;
+        answers_map.insert(
+            id,
+            Answer {
+                submitter: principal1,
+                submission_time: 1631075074,
+                content: ByteBuf::from(bytes),
+                votes: v.clone(),
+                commitments: vec![],
+                vote_changes: vec![],
+                revisions: vec![],
+            },
+        );
+        answers_map.insert(
+            id + 1,
+            Answer {
+                submitter: principal1,
+                submission_time: 1631075073,
+                content: ByteBuf::from(bytes),
+                votes: v.clone(),
+                commitments: vec![],
+                vote_changes: vec![],
+                revisions: vec![],
+            },
+        );
+        answers_map.insert(
+            id + 2,
+            Answer {
+                submitter: principal2,
+                submission_time: 1631075074,
+                content: ByteBuf::from(bytes),
+                votes: v.clone(),
+                commitments: vec![],
+                vote_changes: vec![],
+                revisions: vec![],
+            },
+        );
+        answers_map.insert(
+            id + 3,
+            Answer {
+                submitter: principal2,
+                submission_time: 1631075073,
+                content: ByteBuf::from(bytes),
+                votes: v.clone(),
+                commitments: vec![],
+                vote_changes: vec![],
+                revisions: vec![],
+            },
+        );
+
+        //let mut tasks = HashMap::new();
+        let mut ans_ids: HashSet<u64> = HashSet::new();
+        ans_ids.insert(1);
+        ans_ids.insert(2);
+        ans_ids.insert(3);
+        ans_ids.insert(4);
+
+        super::STATE.with(|s| {
+            //s.tasks = RefCell::new(tasks.clone());
+            //s.answers = RefCell::new(answers_map.clone());
+            //s.ledger = RefCell::new(ledger.clone());
+            let mut ledger = s.ledger.borrow_mut();
+            ledger.insert(Account::from(principal1), 100);
+            ledger.insert(Account::from(principal2), 200);
+
+            let mut answers_map = s.answers.borrow_mut();
+            answers_map.insert(
+                id,
+                Answer {
+                    submitter: principal1,
+                    submission_time: 1631075074,
+                    content: ByteBuf::from(bytes),
+                    votes: v.clone(),
+                    commitments: vec![],
+                    vote_changes: vec![],
+                    revisions: vec![],
+                },
+            );
+            answers_map.insert(
+                id + 1,
+                Answer {
+                    submitter: principal1,
+                    submission_time: 1631075073,
+                    content: ByteBuf::from(bytes),
+                    votes: v.clone(),
+                    commitments: vec![],
+                    vote_changes: vec![],
+                    revisions: vec![],
+                },
+            );
+            answers_map.insert(
+                id + 2,
+                Answer {
+                    submitter: principal2,
+                    submission_time: 1631075074,
+                    content: ByteBuf::from(bytes),
+                    votes: v.clone(),
+                    commitments: vec![],
+                    vote_changes: vec![],
+                    revisions: vec![],
+                },
+            );
+            answers_map.insert(
+                id + 3,
+                Answer {
+                    submitter: principal2,
+                    submission_time: 1631075073,
+                    content: ByteBuf::from(bytes),
+                    votes: v.clone(),
+                    commitments: vec![],
+                    vote_changes: vec![],
+                    revisions: vec![],
+                },
+            );
+
+            let mut tasks = s.tasks.borrow_mut();
+            tasks.insert(
+                id,
+                TaskInternal {
+                    submitter: principal1,
+                    task_type: TaskType::TranslateText,
+                    payload: ByteBuf::from(bytes),
+                    answer_deadline: 1631075080,
+                    vote_deadline: 1631075140,
+                    reward: 12,
+                    answers: ans_ids.clone(),
+                    status: TaskStatus::Open,
+                    cancellation_policy: CancellationPolicy::WithoutAnswers,
+                    created_at: 1631075000,
+                    sponsors: vec![],
+                    payout_scheme: PayoutScheme::WinnerTakesAll,
+                    payouts: vec![],
+                    vote_weighting: VoteWeighting::Equal,
+                    language: None,
+                    min_skill_score: None,
+                    commit_reveal: None,
+                    answer_visibility: AnswerVisibility::Public,
+                    settlement_mode: SettlementMode::Vote,
+                    dispute_deadline: None,
+                    payouts_released: false,
+                    dispute: None,
+                    voter_pool: None,
+                    voter_rewards: vec![],
+                    quorum: None,
+                    review_extended: false,
+                    close_reason: None,
+                    conflict_of_interest: ConflictOfInterest::default(),
+                },
+            );
+            tasks.insert(
+                id + 1,
+                TaskInternal {
+                    submitter: principal1,
+                    task_type: TaskType::TranslateText,
+                    payload: ByteBuf::from(bytes),
+                    answer_deadline: 1631075083,
+                    vote_deadline: 1631075143,
+                    reward: 11,
+                    answers: ans_ids.clone(),
+                    status: TaskStatus::Open,
+                    cancellation_policy: CancellationPolicy::WithoutAnswers,
+                    created_at: 1631075000,
+                    sponsors: vec![],
+                    payout_scheme: PayoutScheme::WinnerTakesAll,
+                    payouts: vec![],
+                    vote_weighting: VoteWeighting::Equal,
+                    language: None,
+                    min_skill_score: None,
+                    commit_reveal: None,
+                    answer_visibility: AnswerVisibility::Public,
+                    settlement_mode: SettlementMode::Vote,
+                    dispute_deadline: None,
+                    payouts_released: false,
+                    dispute: None,
+                    voter_pool: None,
+                    voter_rewards: vec![],
+                    quorum: None,
+                    review_extended: false,
+                    close_reason: None,
+                    conflict_of_interest: ConflictOfInterest::default(),
+                },
+            );
+            tasks.insert(
+                id + 2,
+                TaskInternal {
+                    submitter: principal2,
+                    task_type: TaskType::TranslateText,
+                    payload: ByteBuf::from(bytes),
+                    answer_deadline: 1631075085,
+                    vote_deadline: 1631075145,
+                    reward: 10,
+                    answers: ans_ids.clone(),
+                    status: TaskStatus::Open,
+                    cancellation_policy: CancellationPolicy::WithoutAnswers,
+                    created_at: 1631075000,
+                    sponsors: vec![],
+                    payout_scheme: PayoutScheme::WinnerTakesAll,
+                    payouts: vec![],
+                    vote_weighting: VoteWeighting::Equal,
+                    language: None,
+                    min_skill_score: None,
+                    commit_reveal: None,
+                    answer_visibility: AnswerVisibility::Public,
+                    settlement_mode: SettlementMode::Vote,
+                    dispute_deadline: None,
+                    payouts_released: false,
+                    dispute: None,
+                    voter_pool: None,
+                    voter_rewards: vec![],
+                    quorum: None,
+                    review_extended: false,
+                    close_reason: None,
+                    conflict_of_interest: ConflictOfInterest::default(),
+                },
+            );
+        });
+
+        // Request from unregistered principal
+        let result = get_task_impl(principal3, 1631075070, 1);
+        assert_eq!(result, Err(ButlerError::NotRegistered));
+
+        // Request for valid task
+        let mut answers = Vec::new();
+        for ans_id in ans_ids {
+            if let Some(ans_ref) = answers_map.get(&ans_id) {
+                answers.push(AnswerWithId{
+                    answer_id: ans_id,
+                    answer: ans_ref.clone(),
+                    content_hidden: false,
+                });
+            }
+        }
+        let expected_result = Task {
+            submitter: principal1,
+            task_type: TaskType::TranslateText,
+            payload: ByteBuf::from(bytes),
+            answer_deadline: 1631075080,
+            vote_deadline: 1631075140,
+            reward: 12,
+            answers,
+            status: TaskStatus::Open,
+            cancellation_policy: CancellationPolicy::WithoutAnswers,
+            created_at: 1631075000,
+            sponsors: vec![],
+            payout_scheme: PayoutScheme::WinnerTakesAll,
+            payouts: vec![],
+            vote_weighting: VoteWeighting::Equal,
+            language: None,
+            min_skill_score: None,
+            commit_reveal: None,
+            answer_visibility: AnswerVisibility::Public,
+            settlement_mode: SettlementMode::Vote,
+            dispute_deadline: None,
+            payouts_released: false,
+            dispute: None,
+            voter_pool: None,
+            voter_rewards: vec![],
+            quorum: None,
+            review_extended: false,
+            close_reason: None,
+            conflict_of_interest: ConflictOfInterest::default(),
+        };
+        let result = get_task_impl(principal1, 1631075070, 1);
+        assert_eq!(result, Ok(expected_result));
+
+        // Request for invalid task
+        let result = get_task_impl(principal1, 1631075070, 10);
+        assert_eq!(result, Err(ButlerError::TaskNotFound(10)));
+    }
+
+    #[test]
+    fn test_answer_visibility() {
+        let (principal1, principal2, principal3) = principals();
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            for principal in &[principal1, principal2, principal3] {
+                ledger.insert(Account::from(*principal), 0);
+            }
+            let mut tasks = s.tasks.borrow_mut();
+            tasks.insert(
+                1,
+                TaskInternal {
+                    answer_visibility: AnswerVisibility::Hidden,
+                    ..task(principal1, &[1, 2])
+                },
+            );
+            let mut answers = s.answers.borrow_mut();
+            answers.insert(1, answer(principal2));
+            answers.insert(2, answer(principal3));
+        });
+        let hidden = |caller, now| {
+            let mut hidden = get_task_impl(caller, now, 1)
+                .unwrap()
+                .answers
+                .into_iter()
+                .filter(|answer| answer.content_hidden)
+                .map(|answer| (answer.answer_id, answer.answer.content.len()))
+                .collect::<Vec<_>>();
+            hidden.sort_unstable();
+            hidden
+        };
+
+        // Until the answer deadline, answerers only see their own answers,
+        // while the submitter sees all of them.
+        assert_eq!(hidden(principal2, 200), vec![(2, 0)]);
+        assert_eq!(hidden(principal3, 200), vec![(1, 0)]);
+        assert_eq!(hidden(principal1, 200), vec![]);
+
+        // Afterwards, everyone sees all answers.
+        assert_eq!(hidden(principal2, 201), vec![]);
+        assert_eq!(hidden(principal3, 201), vec![]);
+    }
+
+    #[test]
+    fn test_review_phase() {
+        let (principal1, principal2, principal3) = principals();
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            for principal in &[principal1, principal2, principal3] {
+                ledger.insert(Account::from(*principal), 0);
+            }
+            s.tasks.borrow_mut().insert(1, task(principal1, &[1]));
+            s.answers.borrow_mut().insert(1, answer(principal2));
+            s.answer_tasks.borrow_mut().insert(1, 1);
+        });
+
+        // Votes are only accepted during the review phase, which starts
+        // once the answer deadline has passed and ends at the vote deadline.
+        assert_eq!(
+            vote_impl(principal3, 200, 1, 1, Ballot::Choice(Choice::Yes), None),
+            Err(ButlerError::VotingNotOpen { opens_at: 200 })
+        );
+        assert_eq!(
+            vote_impl(principal3, 1001, 1, 1, Ballot::Choice(Choice::Yes), None),
+            Err(ButlerError::DeadlinePassed { deadline: 1000 })
+        );
+
+        super::STATE.with(|s| {
+            start_review(s, 1, 200);
+            assert_eq!(s.tasks.borrow()[&1].status, TaskStatus::Open);
+            start_review(s, 1, 201);
+            assert_eq!(s.tasks.borrow()[&1].status, TaskStatus::InReview);
+            assert_eq!(s.deadlines.borrow().get(&1000), Some(&vec![1]));
+        });
+        assert_eq!(
+            vote_impl(principal3, 201, 1, 1, Ballot::Choice(Choice::Yes), None),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_accept_answer() {
+        let (principal1, principal2, principal3) = principals();
+        let principal4 = Principal::from_slice(&[4]);
+        let vote = |voter, choice| Vote {
+            voter,
+            choice,
+            weight: 1,
+            stake: 0,
+        };
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            for principal in &[principal1, principal2, principal3, principal4] {
+                ledger.insert(Account::from(*principal), 0);
+            }
+            ledger.insert(escrow_account(1), 50);
+            s.tasks.borrow_mut().insert(
+                1,
+                TaskInternal {
+                    sponsors: vec![Sponsor {
+                        sponsor: principal1,
+                        amount: 50,
+                    }],
+                    ..task(principal1, &[1, 2])
+                },
+            );
+            let mut answers = s.answers.borrow_mut();
+            answers.insert(
+                1,
+                Answer {
+                    votes: vec![vote(principal3, Choice::Yes)],
+                    ..answer(principal2)
+                },
+            );
+            answers.insert(
+                2,
+                Answer {
+                    votes: vec![vote(principal3, Choice::No)],
+                    ..answer(principal4)
+                },
+            );
+            schedule(&mut s.deadlines.borrow_mut(), 200, 1);
+        });
+
+        // Only the submitter of a task in acceptance mode can accept an
+        // answer, until the end of the grace period.
+        assert_eq!(
+            accept_answer_impl(principal2, 150, 1, 1),
+            Err(ButlerError::NotSubmitter(1))
+        );
+        assert_eq!(
+            accept_answer_impl(principal1, 150, 1, 1),
+            Err(ButlerError::AcceptanceNotEnabled(1))
+        );
+        super::STATE.with(|s| {
+            s.tasks.borrow_mut().get_mut(&1).unwrap().settlement_mode =
+                SettlementMode::SubmitterAccepts { grace_period: 30 };
+        });
+        assert_eq!(
+            accept_answer_impl(principal1, 231, 1, 1),
+            Err(ButlerError::DeadlinePassed { deadline: 230 })
+        );
+        assert_eq!(
+            accept_answer_impl(principal1, 150, 1, 3),
+            Err(ButlerError::AnswerNotInTask {
+                answer_id: 3,
+                task_id: 1
+            })
+        );
+
+        // Accepting an answer pays the whole reward to its author and
+        // settles the task like a vote would.
+        assert_eq!(accept_answer_impl(principal1, 150, 1, 1), Ok(()));
+        super::STATE.with(|s| {
+            let ledger = s.ledger.borrow();
+            assert_eq!(ledger.get(&Account::from(principal2)), Some(&50));
+            assert_eq!(ledger.get(&escrow_account(1)).copied().unwrap_or(0), 0);
+            let transactions = s.transactions.borrow();
+            assert_eq!(transactions.len(), 1);
+            assert_eq!(transactions[0].kind, TransactionKind::Payout);
+            assert_eq!(transactions[0].from, Some(escrow_account(1)));
+            assert_eq!(transactions[0].to, Some(Account::from(principal2)));
+            assert_eq!(transactions[0].amount, 50);
+            assert_eq!(transactions[0].task_id, Some(1));
+
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&1].status, TaskStatus::Closed);
+            assert_eq!(tasks[&1].close_reason, Some(CloseReason::Accepted));
+            assert!(tasks[&1].payouts_released);
+            assert!(s.deadlines.borrow().is_empty());
+
+            let reputation = s.reputation.borrow();
+            assert_eq!(reputation.get(&principal2), Some(&REPUTATION_FOR_PAYOUT));
+            assert_eq!(
+                reputation.get(&principal3),
+                Some(&REPUTATION_FOR_WINNING_VOTE)
+            );
+            assert_eq!(
+                reputation.get(&principal4),
+                Some(&-REPUTATION_FOR_REJECTED_ANSWER)
+            );
+            assert_eq!(reputation.get(&principal1), None);
+        });
+        assert_eq!(
+            accept_answer_impl(principal1, 150, 1, 2),
+            Err(ButlerError::TaskNotOpen(1))
+        );
+    }
+
+    #[test]
+    fn test_image_validation() {
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        png.extend_from_slice(&[0, 0, 0, 13]);
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&640u32.to_be_bytes());
+        png.extend_from_slice(&480u32.to_be_bytes());
+        png.extend_from_slice(&[8, 2, 0, 0, 0, 0, 0, 0, 0]);
+        png.extend_from_slice(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&[0x20, 0x00, 0x10, 0x00, 0, 0, 0, 0x3b]);
+
+        let jpeg = vec![
+            0xff, 0xd8, // SOI
+            0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, // APP0
+            0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 0x20, 0x00, 0x40, 0x01, 0x01, 0x11,
+            0x00, // SOF0
+            0xff, 0xd9, // EOI
+        ];
+
+        assert_eq!(
+            image_dimensions(&ImageFormat::Png, &png),
+            Ok(ImageDimensions {
+                width: 640,
+                height: 480
+            })
+        );
+        assert_eq!(
+            image_dimensions(&ImageFormat::Gif, &gif),
+            Ok(ImageDimensions {
+                width: 32,
+                height: 16
+            })
+        );
+        assert_eq!(
+            image_dimensions(&ImageFormat::Jpeg, &jpeg),
+            Ok(ImageDimensions {
+                width: 64,
+                height: 32
+            })
+        );
+
+        // Wrong format and truncated images are rejected.
+        assert!(image_dimensions(&ImageFormat::Jpeg, &png).is_err());
+        assert!(image_dimensions(&ImageFormat::Png, &png[..png.len() - 1]).is_err());
+        assert!(image_dimensions(&ImageFormat::Jpeg, &jpeg[..jpeg.len() - 2]).is_err());
+
+        let input = EditImageInput {
+            source: ImageSource::Data(ByteBuf::from(png.clone())),
+            format: ImageFormat::Png,
+            instructions: "Remove the background".to_string(),
+            target_dimensions: Some(ImageDimensions {
+                width: 32,
+                height: 16,
+            }),
+            target_format: ImageFormat::Gif,
+        };
+        assert!(input.validate().is_ok());
+        assert!(input.validate_answer(&gif).is_ok());
+        assert!(input.validate_answer(&png).is_err());
+
+        let input = EditImageInput {
+            source: ImageSource::Url("http://example.com/cat.png".to_string()),
+            ..input
+        };
+        assert!(input.validate().is_err());
+
+        // Image answers may exceed the limit for text, up to their own.
+        let (principal1, _, _) = principals();
+        let mut image_task = task(principal1, &[]);
+        image_task.task_type = TaskType::EditImage;
+        image_task.payload = ByteBuf::from(
+            Encode!(&EditImageInput {
+                source: ImageSource::Data(ByteBuf::from(png)),
+                target_dimensions: None,
+                ..input
+            })
+            .unwrap(),
+        );
+        let large_gif = |size: usize| {
+            let mut image = gif.clone();
+            image.splice(gif.len() - 1..gif.len() - 1, vec![0; size - gif.len()]);
+            image
+        };
+        assert!(validate_content(&image_task, &large_gif(MAX_CONTENT_SIZE + 1)).is_ok());
+        assert_eq!(
+            validate_content(&image_task, &large_gif(MAX_IMAGE_SIZE + 1)),
+            Err(ButlerError::PayloadTooLarge {
+                max_size: MAX_IMAGE_SIZE as u64,
+                size: MAX_IMAGE_SIZE as u64 + 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_register() {
+        let (principal1, principal2, _) = principals();
+        assert_eq!(register_impl(principal1, 5), Ok(()));
+        assert_eq!(
+            register_impl(principal1, 6),
+            Err(ButlerError::AlreadyRegistered)
+        );
+
+        // Tokens can be sent to principals that have not registered yet,
+        // which keeps their grant intact.
+        let arg = TransferArg {
+            from_subaccount: None,
+            to: Account::from(principal2),
+            amount: Nat::from(10u64),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        assert_eq!(icrc1_transfer_impl(principal1, 7, arg), Ok(Nat::from(1u64)));
+        assert_eq!(register_impl(principal2, 8), Ok(()));
+        assert_eq!(
+            icrc1_balance_of_impl(Account::from(principal2)),
+            Ok(Nat::from(INITIAL_TOKENS + 10))
+        );
+        assert_eq!(
+            movements(0),
+            vec![
+                (
+                    TransactionKind::RegistrationGrant,
+                    None,
+                    Some(Account::from(principal1)),
+                    INITIAL_TOKENS
+                ),
+                (
+                    TransactionKind::Transfer,
+                    Some(Account::from(principal1)),
+                    Some(Account::from(principal2)),
+                    10
+                ),
+                (
+                    TransactionKind::RegistrationGrant,
+                    None,
+                    Some(Account::from(principal2)),
+                    INITIAL_TOKENS
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_transfer() {
+        let (principal1, principal2, principal3) = principals();
+        let account1 = Account::from(principal1);
+        let account2 = Account::from(principal2);
+        let account3 = Account::from(principal3);
+
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            ledger.insert(account1.clone(), 100);
+            ledger.insert(account2.clone(), u64::MAX - 10);
+        });
+
+        let memo = Some(ByteBuf::from(vec![1, 2, 3]));
+        assert_eq!(
+            transfer_impl(
+                account1.clone(),
+                5,
+                account2.clone(),
+                10,
+                memo.clone(),
+                None
+            ),
+            Ok(0)
+        );
+        assert_eq!(
+            transfer_impl(account2.clone(), 6, account1.clone(), 20, None, None),
+            Ok(1)
+        );
+
+        // Senders without tokens and the canister's own accounts
+        assert_eq!(
+            transfer_impl(account3.clone(), 7, account1.clone(), 1, None, None),
+            Err(ButlerError::InsufficientBalance {
+                balance: 0,
+                required: 1
+            })
+        );
+        assert_eq!(
+            transfer_impl(account1.clone(), 7, escrow_account(1), 1, None, None),
+            Err(ButlerError::ReservedAccount(escrow_account(1)))
+        );
+
+        // Insufficient balance, overflow and oversized memo
+        assert_eq!(
+            transfer_impl(account1.clone(), 7, account2.clone(), 111, None, None),
+            Err(ButlerError::InsufficientBalance {
+                balance: 110,
+                required: 111
+            })
+        );
+        assert_eq!(
+            transfer_impl(account1.clone(), 7, account2.clone(), 100, None, None),
+            Err(ButlerError::BalanceOverflow(account2.clone()))
+        );
+        assert_eq!(
+            transfer_impl(
+                account1.clone(),
+                7,
+                account2.clone(),
+                1,
+                Some(ByteBuf::from(vec![0; 33])),
+                None
+            ),
+            Err(ButlerError::PayloadTooLarge {
+                max_size: 32,
+                size: 33
+            })
+        );
+
+        // ICRC-1 transfers to a subaccount, with deduplication
+        let subaccount2 = Account {
+            owner: principal2,
+            subaccount: Some(ByteBuf::from(vec![7; 32])),
+        };
+        let arg = TransferArg {
+            from_subaccount: Some(ByteBuf::from(vec![0; 32])),
+            to: subaccount2.clone(),
+            amount: Nat::from(30u64),
+            fee: None,
+            memo: None,
+            created_at_time: Some(8),
+        };
+        assert_eq!(
+            icrc1_transfer_impl(principal1, 9, arg.clone()),
+            Ok(Nat::from(2u64))
+        );
+        assert_eq!(
+            icrc1_transfer_impl(principal1, 9, arg.clone()),
+            Err(TransferError::Duplicate {
+                duplicate_of: Nat::from(2u64)
+            })
+        );
+        assert_eq!(
+            icrc1_transfer_impl(
+                principal1,
+                9,
+                TransferArg {
+                    fee: Some(Nat::from(1u64)),
+                    ..arg.clone()
+                }
+            ),
+            Err(TransferError::BadFee {
+                expected_fee: Nat::from(0u64)
+            })
+        );
+        // Escrow accounts are rejected, as with `transfer`.
+        assert!(matches!(
+            icrc1_transfer_impl(
+                principal1,
+                9,
+                TransferArg {
+                    to: escrow_account(1),
+                    ..arg.clone()
+                }
+            ),
+            Err(TransferError::GenericError { message, .. })
+                if message.starts_with("ReservedAccount")
+        ));
+        assert_eq!(
+            icrc1_transfer_impl(
+                principal1,
+                9,
+                TransferArg {
+                    amount: Nat::from(81u64),
+                    created_at_time: None,
+                    ..arg
+                }
+            ),
+            Err(TransferError::InsufficientFunds {
+                balance: Nat::from(80u64)
+            })
+        );
+        assert_eq!(icrc1_balance_of_impl(subaccount2), Ok(Nat::from(30u64)));
+        assert_eq!(
+            icrc1_balance_of_impl(Account {
+                owner: principal2,
+                subaccount: Some(ByteBuf::from(vec![7; 31])),
+            }),
+            Err(ButlerError::InvalidSubaccount)
+        );
+
+        super::STATE.with(|s| {
+            let ledger = s.ledger.borrow();
+            assert_eq!(ledger.get(&account1), Some(&80));
+            assert_eq!(ledger.get(&account2), Some(&(u64::MAX - 20)));
+        });
+
+        let first = Transaction {
+            index: 0,
+            kind: TransactionKind::Transfer,
+            from: Some(account1.clone()),
+            to: Some(account2.clone()),
+            amount: 10,
+            task_id: None,
+            memo,
+            created_at_time: None,
+            timestamp: 5,
+        };
+        let second = Transaction {
+            index: 1,
+            kind: TransactionKind::Transfer,
+            from: Some(account2),
+            to: Some(account1),
+            amount: 20,
+            task_id: None,
+            memo: None,
+            created_at_time: None,
+            timestamp: 6,
+        };
+        assert_eq!(
+            get_transactions(0, 2),
+            TransactionRange {
+                total: 3,
+                transactions: vec![first, second.clone()],
+            }
+        );
+        let history = get_principal_transactions(principal1, 1, 1);
+        assert_eq!(
+            history,
+            TransactionRange {
+                total: 3,
+                transactions: vec![second],
+            }
+        );
+        assert_eq!(get_principal_transactions(principal3, 0, 10).total, 0);
+        assert_eq!(icrc1_total_supply(), Nat::from(u64::MAX as u128 + 90));
+    }
+
+    #[test]
+    fn test_transaction_log() {
+        let (principal1, principal2, _) = principals();
+        let account1 = Account::from(principal1);
+        let subaccount1 = Account {
+            owner: principal1,
+            subaccount: Some(ByteBuf::from(vec![1; 32])),
+        };
+        let mut ledger = HashMap::new();
+        let mut log = TransactionLog::default();
+        let mut transfer = |from: Option<&Account>, to: &Account, amount| {
+            let transaction = Transaction::new(
+                TransactionKind::Transfer,
+                from.cloned(),
+                Some(to.clone()),
+                amount,
+                0,
+            );
+            execute(&mut ledger, &mut log, transaction).unwrap();
+        };
+        transfer(None, &account1, 100);
+        transfer(Some(&account1), &subaccount1, 30);
+        transfer(Some(&subaccount1), &escrow_account(1), 20);
+
+        // Transactions between two accounts of the same principal are only
+        // indexed once.
+        assert_eq!(log.history(&principal1), &[0, 1, 2]);
+        assert_eq!(log.history(&canister_id()), &[2]);
+        assert_eq!(log.history(&principal2), &[] as &[BlockIndex]);
+
+        // Reverted transactions leave the index.
+        revert_transactions(&mut ledger, &mut log, 2);
+        assert_eq!(log.history(&principal1), &[0, 1]);
+        assert_eq!(log.history(&canister_id()), &[] as &[BlockIndex]);
+        assert_eq!(ledger.get(&subaccount1), Some(&30));
+
+        // The index is rebuilt on upgrade.
+        super::STATE.with(|s| {
+            restore_state(
+                s,
+                StableStateV1 {
+                    next_task_id: 0,
+                    tasks: BTreeMap::new(),
+                    answers: HashMap::new(),
+                    next_answer_id: 0,
+                    ledger,
+                    registered: HashSet::new(),
+                    transactions: log.into_inner(),
+                    reputation: HashMap::new(),
+                    skills: HashMap::new(),
+                },
+            );
+        });
+        let history = get_principal_transactions(principal1, 1, 10);
+        assert_eq!(history.total, 2);
+        assert_eq!(history.transactions.len(), 1);
+        assert_eq!(history.transactions[0].index, 1);
+    }
+
+    #[test]
+    fn test_extend_deadline() {
+        let (principal1, principal2, _) = principals();
+        let max_duration = u64::try_from(MAX_DURATION.as_nanos()).unwrap();
+        super::STATE.with(|s| {
+            let mut tasks = s.tasks.borrow_mut();
+            tasks.insert(1, task(principal1, &[]));
+            tasks.insert(
+                2,
+                TaskInternal {
+                    status: TaskStatus::InReview,
+                    ..task(principal1, &[])
+                },
+            );
+            schedule(&mut s.deadlines.borrow_mut(), 200, 1);
+        });
+
+        assert_eq!(
+            extend_deadline_impl(principal2, 100, 1, 50),
+            Err(ButlerError::NotSubmitter(1))
+        );
+        assert_eq!(
+            extend_deadline_impl(principal1, 100, 2, 50),
+            Err(ButlerError::TaskNotOpen(2))
+        );
+        assert_eq!(
+            extend_deadline_impl(principal1, 201, 1, 50),
+            Err(ButlerError::DeadlinePassed { deadline: 200 })
+        );
+        assert_eq!(
+            extend_deadline_impl(principal1, 100, 1, max_duration),
+            Err(ButlerError::ExtensionTooLong {
+                max_deadline: max_duration
+            })
+        );
+
+        // Both deadlines move, and the task is rescheduled for the new one.
+        assert_eq!(extend_deadline_impl(principal1, 100, 1, 50), Ok(250));
+        super::STATE.with(|s| {
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&1].answer_deadline, 250);
+            assert_eq!(tasks[&1].vote_deadline, 1050);
+            assert_eq!(s.deadlines.borrow().keys().collect::<Vec<_>>(), vec![&250]);
+            assert_eq!(s.deadlines.borrow()[&250], vec![1]);
+        });
+        assert_eq!(
+            extend_deadline_impl(principal1, 100, 1, max_duration - 250),
+            Ok(max_duration)
+        );
+    }
+
+    #[test]
+    fn test_add_reward() {
+        let (principal1, principal2, principal3) = principals();
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            ledger.insert(Account::from(principal1), 100);
+            ledger.insert(Account::from(principal2), 30);
+            ledger.insert(escrow_account(1), 50);
+            let mut tasks = s.tasks.borrow_mut();
+            tasks.insert(
+                1,
+                TaskInternal {
+                    sponsors: vec![Sponsor {
+                        sponsor: principal1,
+                        amount: 50,
+                    }],
+                    ..task(principal1, &[])
+                },
+            );
+            tasks.insert(
+                2,
+                TaskInternal {
+                    status: TaskStatus::Closed,
+                    ..task(principal1, &[])
+                },
+            );
+        });
+
+        assert_eq!(
+            add_reward_impl(principal2, 100, 1, 0),
+            Err(ButlerError::InvalidAmount)
+        );
+        assert_eq!(
+            add_reward_impl(principal3, 100, 1, 10),
+            Err(ButlerError::NotRegistered)
+        );
+        assert_eq!(
+            add_reward_impl(principal2, 100, 2, 10),
+            Err(ButlerError::TaskNotOpen(2))
+        );
+        assert_eq!(
+            add_reward_impl(principal2, 201, 1, 10),
+            Err(ButlerError::DeadlinePassed { deadline: 200 })
+        );
+        assert_eq!(
+            add_reward_impl(principal2, 100, 1, 31),
+            Err(ButlerError::InsufficientBalance {
+                balance: 30,
+                required: 31
+            })
+        );
+        assert_eq!(movements(0), vec![]);
+
+        // The submitter and co-sponsors top up the escrowed reward. Each
+        // sponsor's contributions are added up.
+        assert_eq!(add_reward_impl(principal2, 100, 1, 20), Ok(70));
+        assert_eq!(add_reward_impl(principal1, 100, 1, 10), Ok(80));
+        assert_eq!(add_reward_impl(principal2, 100, 1, 10), Ok(90));
+        let escrow = |sponsor: Principal, amount| {
+            (
+                TransactionKind::Escrow,
+                Some(Account::from(sponsor)),
+                Some(escrow_account(1)),
+                amount,
+            )
+        };
+        assert_eq!(
+            movements(0),
+            vec![
+                escrow(principal2, 20),
+                escrow(principal1, 10),
+                escrow(principal2, 10)
+            ]
+        );
+        assert_eq!(balance(&Account::from(principal1)), 90);
+        assert_eq!(balance(&Account::from(principal2)), 0);
+        assert_eq!(balance(&escrow_account(1)), 90);
+        super::STATE.with(|s| {
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&1].reward, 90);
+            assert_eq!(
+                tasks[&1].sponsors,
+                vec![
+                    Sponsor {
+                        sponsor: principal1,
+                        amount: 60,
+                    },
+                    Sponsor {
+                        sponsor: principal2,
+                        amount: 30,
+                    },
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_pro_rata() {
+        let (principal1, principal2, _) = principals();
+        let sponsors = vec![
+            Sponsor {
+                sponsor: principal1,
+                amount: 10,
+            },
+            Sponsor {
+                sponsor: principal2,
+                amount: 20,
+            },
+        ];
+
+        assert_eq!(
+            pro_rata(&sponsors, 30),
+            vec![(principal1, 10), (principal2, 20)]
+        );
+        // The remainder goes to the submitter.
+        assert_eq!(
+            pro_rata(&sponsors, 10),
+            vec![(principal1, 4), (principal2, 6)]
+        );
+        assert_eq!(
+            pro_rata(&sponsors, u64::MAX),
+            vec![
+                (principal1, 6148914691236517205),
+                (principal2, 12297829382473034410)
+            ]
+        );
+
+        // A task without a reward refunds everything to the submitter.
+        let sponsors = vec![Sponsor {
+            sponsor: principal1,
+            amount: 0,
+        }];
+        assert_eq!(pro_rata(&sponsors, 0), vec![(principal1, 0)]);
+    }
+
+    #[test]
+    fn test_cancel_task() {
+        let (principal1, principal2, principal3) = principals();
+        super::STATE.with(|s| {
+            // Without a reward, nothing has to be paid out of escrow.
+            let task = TaskInternal {
+                reward: 0,
+                ..task(principal1, &[])
+            };
+            schedule(&mut s.deadlines.borrow_mut(), task.next_phase_change(), 1);
+            s.tasks.borrow_mut().insert(1, task);
+        });
+
+        assert_eq!(
+            cancel_task_impl(principal2, 100, 1),
+            Err(ButlerError::NotSubmitter(1))
+        );
+        assert_eq!(
+            cancel_task_impl(principal1, 201, 1),
+            Err(ButlerError::DeadlinePassed { deadline: 200 })
+        );
+        assert_eq!(cancel_task_impl(principal1, 100, 1), Ok(()));
+        assert_eq!(
+            cancel_task_impl(principal1, 100, 1),
+            Err(ButlerError::TaskNotOpen(1))
+        );
+        super::STATE.with(|s| {
+            assert_eq!(s.tasks.borrow()[&1].status, TaskStatus::Cancelled);
+            assert!(s.deadlines.borrow().is_empty());
+        });
+
+        // Compensated answerers see their compensation as earnings.
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            ledger.insert(Account::from(principal1), 0);
+            ledger.insert(Account::from(principal2), 0);
+            ledger.insert(Account::from(principal3), 0);
+            ledger.insert(escrow_account(2), 40);
+            let mut answers = s.answers.borrow_mut();
+            for &(answer_id, submitter) in &[(1, principal2), (2, principal3)] {
+                answers.insert(answer_id, answer(submitter));
+                s.answer_tasks.borrow_mut().insert(answer_id, 2);
+                index_insert(&mut s.authored_answers.borrow_mut(), submitter, answer_id);
+            }
+            let task = TaskInternal {
+                reward: 40,
+                cancellation_policy: CancellationPolicy::CompensateAnswerers { percent: 50 },
+                sponsors: vec![Sponsor {
+                    sponsor: principal1,
+                    amount: 40,
+                }],
+                ..task(principal1, &[1, 2])
+            };
+            s.tasks.borrow_mut().insert(2, task);
+        });
+        assert_eq!(cancel_task_impl(principal1, 100, 2), Ok(()));
+        super::STATE.with(|s| {
+            let ledger = s.ledger.borrow();
+            assert_eq!(ledger[&Account::from(principal1)], 20);
+            assert_eq!(ledger[&Account::from(principal2)], 10);
+            assert_eq!(ledger[&Account::from(principal3)], 10);
+            assert!(!ledger.contains_key(&escrow_account(2)));
+        });
+        let answers = get_my_answers_impl(principal2, None, 10).unwrap().answers;
+        assert_eq!(
+            answers,
+            vec![MyAnswer {
+                answer_id: 1,
+                task_id: 2,
+                task_status: TaskStatus::Cancelled,
+                submission_time: 100,
+                earned: 10,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_list_tasks() {
+        let (principal1, principal2, _) = principals();
+        let task = |submitter, reward, answer_deadline| TaskInternal {
+            answer_deadline,
+            vote_deadline: answer_deadline + 100,
+            reward,
+            language: Some(Language::German),
+            ..task(submitter, &[])
+        };
+        super::STATE.with(|s| {
+            s.ledger.borrow_mut().insert(Account::from(principal1), 100);
+            let mut tasks = s.tasks.borrow_mut();
+            for id in 0..5 {
+                tasks.insert(id, task(principal1, 10 * id, 1000 + id));
+            }
+            tasks.insert(5, task(principal2, 100, 2000));
+            tasks.get_mut(&2).unwrap().status = TaskStatus::Closed;
+            let input = TranslateTextInput {
+                input: "x".repeat(PAYLOAD_PREVIEW_LENGTH + 1),
+                language: Language::German,
+            };
+            tasks.get_mut(&5).unwrap().payload = ByteBuf::from(Encode!(&input).unwrap());
+        });
+        let ids = |page: &TaskPage| page.tasks.iter().map(|task| task.id).collect::<Vec<_>>();
+
+        assert_eq!(
+            list_tasks_impl(principal2, None, None, 10),
+            Err(ButlerError::NotRegistered)
+        );
+
+        // Pages go from the newest to the oldest task.
+        let page = list_tasks_impl(principal1, None, None, 4).unwrap();
+        assert_eq!(
+            page.tasks[0],
+            ShortTask {
+                id: 5,
+                submitter: principal2,
+                task_type: TaskType::TranslateText,
+                status: TaskStatus::Open,
+                reward: 100,
+                answer_deadline: 2000,
+                vote_deadline: 2100,
+                answer_count: 0,
+                total_votes: 0,
+                language: Some(Language::German),
+                payload_preview: "x".repeat(PAYLOAD_PREVIEW_LENGTH),
+            }
+        );
+        assert_eq!(ids(&page), vec![5, 4, 3, 2]);
+        assert_eq!(page.next_cursor, Some(2));
+        let page = list_tasks_impl(principal1, None, page.next_cursor, 4).unwrap();
+        assert_eq!(ids(&page), vec![1, 0]);
+        assert_eq!(page.next_cursor, None);
+
+        let filter = TaskFilter {
+            status: Some(TaskStatus::Open),
+            submitter: Some(principal1),
+            min_reward: Some(10),
+            deadline_before: Some(1003),
+            ..TaskFilter::default()
+        };
+        let page = list_tasks_impl(principal1, Some(filter.clone()), None, 1).unwrap();
+        assert_eq!(ids(&page), vec![3]);
+        assert_eq!(page.next_cursor, Some(3));
+        let page = list_tasks_impl(principal1, Some(filter), Some(3), 1).unwrap();
+        assert_eq!(ids(&page), vec![1]);
+        assert_eq!(page.next_cursor, None);
+
+        // A limit of zero still returns one task and a cursor to the next.
+        let page = list_tasks_impl(principal1, None, None, 0).unwrap();
+        assert_eq!(ids(&page), vec![5]);
+        assert_eq!(page.next_cursor, Some(5));
+
+        let filter = TaskFilter {
+            language: Some(Language::French),
+            ..TaskFilter::default()
+        };
+        assert!(list_tasks_impl(principal1, Some(filter), None, 10)
+            .unwrap()
+            .tasks
+            .is_empty());
+    }
+
+    #[test]
+    fn test_my_activity() {
+        let (principal1, principal2, principal3) = principals();
+
+        let ids: BTreeSet<u64> = (0..5).collect();
+        assert_eq!(page(Some(&ids), None, 2), (vec![4, 3], Some(3)));
+        assert_eq!(page(Some(&ids), Some(3), 2), (vec![2, 1], Some(1)));
+        assert_eq!(page(Some(&ids), Some(1), 2), (vec![0], None));
+        assert_eq!(page(Some(&ids), None, 0), (vec![4], Some(4)));
+        assert_eq!(page(None, None, 2), (vec![], None));
+
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            ledger.insert(Account::from(principal1), 100);
+            ledger.insert(Account::from(principal2), 100);
+            s.answers.borrow_mut().insert(1, answer(principal1));
+            s.answer_tasks.borrow_mut().insert(1, 1);
+            index_insert(&mut s.authored_answers.borrow_mut(), principal1, 1);
+            s.tasks.borrow_mut().insert(
+                1,
+                TaskInternal {
+                    status: TaskStatus::InReview,
+                    ..task(principal2, &[1])
+                },
+            );
+        });
+
+        // Votes are indexed when they are cast.
+        assert_eq!(
+            vote_impl(principal2, 500, 1, 1, Ballot::Choice(Choice::Yes), None),
+            Ok(())
+        );
+        let my_vote = MyVote {
+            answer_id: 1,
+            task_id: 1,
+            task_status: TaskStatus::InReview,
+            ballot: Ballot::Choice(Choice::Yes),
+            stake: 0,
+            earned: 0,
+        };
+        assert_eq!(
+            get_my_votes_impl(principal2, None, 10).unwrap().votes,
+            vec![my_vote]
+        );
+        assert!(get_my_votes_impl(principal1, None, 10)
+            .unwrap()
+            .votes
+            .is_empty());
+
+        // Answers report their payouts once they are released.
+        super::STATE.with(|s| {
+            let mut tasks = s.tasks.borrow_mut();
+            let task = tasks.get_mut(&1).unwrap();
+            task.status = TaskStatus::Closed;
+            task.payouts = vec![Payout {
+                answer_id: 1,
+                recipient: principal1,
+                amount: 50,
+            }];
+        });
+        let my_answer = |earned| MyAnswer {
+            answer_id: 1,
+            task_id: 1,
+            task_status: TaskStatus::Closed,
+            submission_time: 100,
+            earned,
+        };
+        assert_eq!(
+            get_my_answers_impl(principal1, None, 10).unwrap().answers,
+            vec![my_answer(0)]
+        );
+        super::STATE.with(|s| {
+            s.tasks.borrow_mut().get_mut(&1).unwrap().payouts_released = true;
+        });
+        assert_eq!(
+            get_my_answers_impl(principal1, None, 10).unwrap().answers,
+            vec![my_answer(50)]
+        );
+        assert!(get_my_tasks_impl(principal1, None, 10)
+            .unwrap()
+            .tasks
+            .is_empty());
+
+        // Sealed votes are listed as their hashes, and left out if they were
+        // not revealed by settlement.
+        super::STATE.with(|s| {
+            s.ledger.borrow_mut().insert(Account::from(principal3), 100);
+            s.answers.borrow_mut().insert(2, answer(principal1));
+            s.answer_tasks.borrow_mut().insert(2, 2);
+            s.tasks.borrow_mut().insert(
+                2,
+                TaskInternal {
+                    status: TaskStatus::InReview,
+                    commit_reveal: Some(CommitReveal {
+                        reveal_period: 100,
+                        penalty: 0,
+                    }),
+                    ..task(principal2, &[2])
+                },
+            );
+        });
+        let hash = ByteBuf::from(commitment_hash(&principal3, &Choice::No, &[4, 2]));
+        assert_eq!(
+            vote_impl(
+                principal3,
+                500,
+                2,
+                2,
+                Ballot::Commitment(hash.clone()),
+                None
+            ),
+            Ok(())
+        );
+        let votes = get_my_votes_impl(principal3, None, 10).unwrap().votes;
+        assert_eq!(votes.len(), 1);
+        assert_eq!(votes[0].ballot, Ballot::Commitment(hash));
+        super::STATE
+            .with(|s| s.tasks.borrow_mut().get_mut(&2).unwrap().status = TaskStatus::Closed);
+        assert!(get_my_votes_impl(principal3, None, 10)
+            .unwrap()
+            .votes
+            .is_empty());
+    }
+
+    #[test]
+    fn test_legacy_state() {
+        let (principal1, principal2, _) = principals();
+        let max_duration = u64::try_from(MAX_DURATION.as_nanos()).unwrap();
+        let payload = Encode!(&TranslateTextInput {
+            input: "Hello".to_string(),
+            language: Language::German,
+        })
+        .unwrap();
+        let legacy_task = |deadline, status| LegacyTaskInternal {
+            submitter: principal1,
+            task_type: TaskType::TranslateText,
+            payload: ByteBuf::from(payload.clone()),
+            deadline,
+            reward: 30,
+            answers: vec![1].into_iter().collect(),
+            status,
+        };
+        let legacy_answer = LegacyAnswer {
+            submitter: principal2,
+            submission_time: 100,
+            content: ByteBuf::from(vec![1]),
+            votes: vec![LegacyVote {
+                voter: principal1,
+                choice: Choice::Yes,
+            }],
+        };
+        let legacy: LegacyStableState = (
+            3,
+            vec![
+                (1, legacy_task(max_duration + 500, TaskStatus::Open)),
+                (2, legacy_task(400, TaskStatus::Closed)),
+            ]
+            .into_iter()
+            .collect(),
+            vec![(1, legacy_answer)].into_iter().collect(),
+            2,
+            vec![(principal1, 50), (principal2, 80)]
+                .into_iter()
+                .collect(),
+        );
+
+        // The unversioned layout is told apart from the current one.
+        let bytes = Encode!(&legacy.0, &legacy.1, &legacy.2, &legacy.3, &legacy.4).unwrap();
+        assert!(Decode!(&bytes, StableState).is_err());
+        let decoded = Decode!(
+            &bytes,
+            TaskId,
+            HashMap<TaskId, LegacyTaskInternal>,
+            HashMap<AnswerId, LegacyAnswer>,
+            AnswerId,
+            HashMap<Principal, Amount>
+        )
+        .unwrap();
+
+        let state = migrate_legacy_state(decoded);
+        let bytes = Encode!(&StableState::V1(state)).unwrap();
+        let StableState::V1(state) = Decode!(&bytes, StableState).unwrap();
+        super::STATE.with(|s| {
+            restore_state(s, state);
+            assert_eq!(*s.next_task_id.borrow(), 3);
+            assert_eq!(*s.next_answer_id.borrow(), 2);
+
+            // Only the reward of the open task is still to be paid out.
+            let ledger = s.ledger.borrow();
+            assert_eq!(ledger[&Account::from(principal1)], 50);
+            assert_eq!(ledger[&Account::from(principal2)], 80);
+            assert_eq!(ledger[&escrow_account(1)], 30);
+            assert!(!ledger.contains_key(&escrow_account(2)));
+            assert_eq!(s.registered.borrow().len(), 2);
+
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&1].answer_deadline, max_duration + 500);
+            assert_eq!(tasks[&1].vote_deadline, max_duration + 500);
+            assert_eq!(tasks[&1].created_at, 500);
+            assert_eq!(tasks[&1].language, Some(Language::German));
+            assert_eq!(
+                tasks[&1].sponsors,
+                vec![Sponsor {
+                    sponsor: principal1,
+                    amount: 30,
+                }]
+            );
+            assert!(tasks[&2].payouts_released);
+            assert_eq!(tasks[&2].created_at, 0);
+            assert_eq!(s.answers.borrow()[&1].votes[0].weight, 1);
+
+            // The escrowed reward is recorded as taken from the submitter.
+            let transactions = s.transactions.borrow();
+            assert_eq!(transactions.len(), 1);
+            assert_eq!(transactions[0].kind, TransactionKind::Escrow);
+            assert_eq!(transactions[0].from, Some(Account::from(principal1)));
+            assert_eq!(transactions[0].to, Some(escrow_account(1)));
+            assert_eq!(transactions[0].amount, 30);
+            assert_eq!(transactions[0].task_id, Some(1));
+            assert_eq!(transactions.history(&principal1), &[0]);
+
+            // Derived indexes are rebuilt, and only the open task is scheduled.
+            assert_eq!(
+                s.deadlines.borrow().get(&(max_duration + 500)),
+                Some(&vec![1])
+            );
+            assert_eq!(s.deadlines.borrow().len(), 1);
+            assert_eq!(s.answer_tasks.borrow().get(&1), Some(&2));
+            assert!(s.voted_answers.borrow()[&principal1].contains(&1));
+        });
+    }
+
+    #[test]
+    fn test_pop_due_tasks() {
+        let mut deadlines = BTreeMap::new();
+        schedule(&mut deadlines, 30, 3);
+        schedule(&mut deadlines, 10, 1);
+        schedule(&mut deadlines, 20, 2);
+        schedule(&mut deadlines, 20, 4);
+        schedule(&mut deadlines, 40, 5);
+        unschedule(&mut deadlines, 40, 5);
+
+        // Nothing is due yet.
+        assert!(pop_due_tasks(&mut deadlines, 10, 10).is_empty());
+
+        // Expired tasks are processed in batches, earliest deadline first.
+        assert_eq!(pop_due_tasks(&mut deadlines, 35, 2), vec![1, 4]);
+        assert_eq!(pop_due_tasks(&mut deadlines, 35, 2), vec![2, 3]);
+        assert!(pop_due_tasks(&mut deadlines, 35, 2).is_empty());
+        assert!(deadlines.is_empty());
+
+        // A heartbeat stops processing tasks once its budget is spent.
+        super::STATE.with(|s| {
+            for task_id in 1..=3 {
+                schedule(&mut s.deadlines.borrow_mut(), 10 * task_id, task_id);
+            }
+            let mut budget = 2;
+            process_due_tasks(s, 100, || {
+                budget -= 1;
+                budget >= 0
+            });
+            assert_eq!(
+                s.deadlines.borrow().values().collect::<Vec<_>>(),
+                vec![&vec![3]]
+            );
+            process_due_tasks(s, 100, || true);
+            assert!(s.deadlines.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_escrow_out_of_sync() {
+        let (principal1, principal2, principal3) = principals();
+        let payout = |answer_id, recipient, amount| Payout {
+            answer_id,
+            recipient,
+            amount,
+        };
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            for principal in &[principal1, principal2, principal3] {
+                ledger.insert(Account::from(*principal), 0);
+            }
+            // The escrow of task 1 only holds part of its payouts.
+            ledger.insert(escrow_account(1), 10);
+            ledger.insert(escrow_account(2), 50);
+            let mut answers = s.answers.borrow_mut();
+            for answer_id in 1..=4 {
+                let submitter = if answer_id % 2 == 1 {
+                    principal2
+                } else {
+                    principal3
+                };
+                answers.insert(answer_id, answer(submitter));
+            }
+            let mut tasks = s.tasks.borrow_mut();
+            tasks.insert(
+                1,
+                TaskInternal {
+                    status: TaskStatus::Closed,
+                    payouts: vec![payout(1, principal2, 10), payout(2, principal3, 40)],
+                    dispute_deadline: Some(2000),
+                    ..task(principal1, &[1, 2])
+                },
+            );
+            tasks.insert(
+                2,
+                TaskInternal {
+                    status: TaskStatus::Closed,
+                    payouts: vec![payout(3, principal2, 50)],
+                    dispute_deadline: Some(2001),
+                    ..task(principal1, &[3, 4])
+                },
+            );
+            let mut deadlines = s.deadlines.borrow_mut();
+            schedule(&mut deadlines, 2000, 1);
+            schedule(&mut deadlines, 2001, 2);
+        });
+
+        // Task 1 cannot be settled, so it is set aside as it was and the
+        // heartbeat moves on to task 2.
+        super::STATE.with(|s| {
+            process_due_tasks(s, 3000, || true);
+            assert!(s.deadlines.borrow().is_empty());
+            let tasks = s.tasks.borrow();
+            assert!(!tasks[&1].payouts_released);
+            assert_eq!(tasks[&1].status, TaskStatus::Closed);
+            assert!(tasks[&2].payouts_released);
+
+            let ledger = s.ledger.borrow();
+            assert_eq!(ledger.get(&escrow_account(1)), Some(&10));
+            assert_eq!(ledger.get(&escrow_account(2)), None);
+            assert_eq!(ledger.get(&Account::from(principal2)), Some(&50));
+            assert_eq!(ledger.get(&Account::from(principal3)), Some(&0));
+            let transactions = s.transactions.borrow();
+            assert_eq!(transactions.len(), 1);
+            assert_eq!(transactions[0].task_id, Some(2));
+            assert_eq!(
+                s.reputation.borrow().get(&principal2),
+                Some(&REPUTATION_FOR_PAYOUT)
+            );
+            assert_eq!(s.reputation.borrow().get(&principal3), None);
+        });
+    }
+
+    #[test]
+    fn test_compute_payouts() {
+        let (principal1, principal2, principal3) = principals();
+        let vote = |choice| Vote {
+            voter: Principal::anonymous(),
+            choice,
+            weight: 1,
+            stake: 0,
+        };
+        let answer = |submitter, submission_time, votes| Answer {
+            submission_time,
+            votes,
+            ..answer(submitter)
+        };
+
+        let answer1 = answer(principal1, 3, vec![vote(Choice::Yes)]);
+        let answer2 = answer(
+            principal2,
+            2,
+            vec![vote(Choice::Yes), vote(Choice::Yes), vote(Choice::No)],
+        );
+        let answer3 = answer(principal3, 1, vec![vote(Choice::Yes), vote(Choice::Yes)]);
+        let unvoted = answer(Principal::anonymous(), 0, vec![]);
+
+        // Ties go to the earliest submission and unvoted answers are not ranked.
+        let ranked = rank_answers(vec![
+            (1, &answer1),
+            (2, &answer2),
+            (3, &answer3),
+            (4, &unvoted),
+        ]);
+        assert_eq!(
+            ranked
+                .iter()
+                .map(|a| (a.answer_id, a.net_votes))
+                .collect::<Vec<_>>(),
+            vec![(3, 2), (2, 1), (1, 1)]
+        );
+
+        let payout = |answer_id, recipient, amount| Payout {
+            answer_id,
+            recipient,
+            amount,
+        };
+        assert_eq!(
+            compute_payouts(&PayoutScheme::WinnerTakesAll, 100, &ranked),
+            vec![payout(3, principal3, 100)]
+        );
+        assert_eq!(
+            compute_payouts(&PayoutScheme::TopN(vec![50, 30, 20]), 11, &ranked),
+            vec![
+                payout(3, principal3, 6),
+                payout(2, principal2, 3),
+                payout(1, principal1, 2)
+            ]
+        );
+        // The shares of missing ranks are not paid out.
+        assert_eq!(
+            compute_payouts(&PayoutScheme::TopN(vec![60, 40]), 100, &ranked[..1]),
+            vec![payout(3, principal3, 60)]
+        );
+        assert_eq!(
+            compute_payouts(&PayoutScheme::Proportional, 10, &ranked),
+            vec![
+                payout(3, principal3, 6),
+                payout(2, principal2, 2),
+                payout(1, principal1, 2)
+            ]
+        );
+        assert!(compute_payouts(&PayoutScheme::WinnerTakesAll, 100, &[]).is_empty());
+
+        // Weighted votes are summed instead of counted.
+        let staked = answer(
+            principal1,
+            3,
+            vec![Vote {
+                voter: principal2,
+                choice: Choice::Yes,
+                weight: 5,
+                stake: 5,
+            }],
+        );
+        let ranked = rank_answers(vec![(1, &staked), (3, &answer3)]);
+        assert_eq!(
+            ranked
+                .iter()
+                .map(|a| (a.answer_id, a.net_votes))
+                .collect::<Vec<_>>(),
+            vec![(1, 5), (3, 2)]
+        );
+
+        assert!(PayoutScheme::TopN(vec![50, 40]).validate().is_err());
+        assert!(PayoutScheme::TopN(vec![]).validate().is_err());
+        assert!(PayoutScheme::TopN(vec![50, 50]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_voter_pool() {
+        let (principal1, principal2, principal3) = principals();
+        let vote = |voter, choice, stake| Vote {
+            voter,
+            choice,
+            weight: stake,
+            stake,
+        };
+        let votes = vec![
+            vote(principal1, Choice::Yes, 1),
+            vote(principal2, Choice::Yes, 2),
+            vote(principal3, Choice::No, 30),
+        ];
+
+        // The pool is split by weight among the yes votes.
+        let reward = |voter, amount| VoterReward { voter, amount };
+        assert_eq!(
+            compute_voter_rewards(10, &votes),
+            vec![reward(principal1, 4), reward(principal2, 6)]
+        );
+        assert_eq!(compute_voter_rewards(10, &votes[2..]), vec![]);
+
+        // Only no votes on the winning answer are slashed.
+        let voter_pool = VoterPool {
+            amount: 10,
+            slash_percent: 50,
+        };
+        assert_eq!(voter_pool.validate(&VoteWeighting::Stake), Ok(()));
+        assert!(matches!(
+            voter_pool.validate(&VoteWeighting::Equal),
+            Err(ButlerError::InvalidOptions(_))
+        ));
+        let mut task = TaskInternal {
+            status: TaskStatus::Closed,
+            payouts: vec![Payout {
+                answer_id: 1,
+                recipient: principal1,
+                amount: 50,
+            }],
+            vote_weighting: VoteWeighting::Stake,
+            dispute_deadline: Some(2000),
+            voter_pool: Some(voter_pool),
+            ..task(principal1, &[1, 2])
+        };
+        assert_eq!(task.slashed_stake(1, &votes[2]), 15);
+        assert_eq!(task.slashed_stake(1, &votes[1]), 0);
+        assert_eq!(task.slashed_stake(2, &votes[2]), 0);
+
+        // The winning answer is the top-ranked paid one, even if a later
+        // rank is paid more.
+        task.payouts = vec![
+            Payout {
+                answer_id: 1,
+                recipient: principal1,
+                amount: 20,
+            },
+            Payout {
+                answer_id: 2,
+                recipient: principal2,
+                amount: 30,
+            },
+        ];
+        assert_eq!(task.winning_answer(), Some(1));
+        task.payouts = vec![];
+        assert_eq!(task.winning_answer(), None);
+        assert_eq!(task.slashed_stake(1, &votes[2]), 0);
+    }
+
+    #[test]
+    fn test_voter_pool_settlement() {
+        let (principal1, principal2, principal3) = principals();
+        let principal4 = Principal::from_slice(&[4]);
+        let principal5 = Principal::from_slice(&[5]);
+        let principal6 = Principal::from_slice(&[6]);
+        let principal7 = Principal::from_slice(&[7]);
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            ledger.insert(Account::from(principal1), 0);
+            ledger.insert(Account::from(principal2), 0);
+            ledger.insert(Account::from(principal4), 0);
+            for voter in &[principal3, principal5, principal6, principal7] {
+                ledger.insert(Account::from(*voter), 100);
+            }
+            // The escrow holds the reward and the voter pool.
+            ledger.insert(escrow_account(1), 70);
+            let mut answers = s.answers.borrow_mut();
+            answers.insert(1, answer(principal2));
+            answers.insert(2, answer(principal4));
+            s.tasks.borrow_mut().insert(
+                1,
+                TaskInternal {
+                    status: TaskStatus::InReview,
+                    sponsors: vec![Sponsor {
+                        sponsor: principal1,
+                        amount: 50,
+                    }],
+                    vote_weighting: VoteWeighting::Stake,
+                    voter_pool: Some(VoterPool {
+                        amount: 20,
+                        slash_percent: 50,
+                    }),
+                    ..task(principal1, &[1, 2])
+                },
+            );
+            schedule(&mut s.deadlines.borrow_mut(), 1000, 1);
+        });
+
+        let vote = |voter, answer_id, choice, stake| {
+            vote_impl(
+                voter,
+                500,
+                answer_id,
+                1,
+                Ballot::Choice(choice),
+                Some(stake),
+            )
+        };
+        assert_eq!(vote(principal3, 1, Choice::Yes, 30), Ok(()));
+        assert_eq!(vote(principal5, 1, Choice::Yes, 10), Ok(()));
+        assert_eq!(vote(principal6, 1, Choice::No, 20), Ok(()));
+        assert_eq!(vote(principal7, 2, Choice::Yes, 10), Ok(()));
+        assert_eq!(balance(&escrow_account(1)), 70 + 70);
+
+        // Answer 1 wins with a net stake of 20. Its payout and the stakes are
+        // held until the dispute deadline.
+        let dispute_deadline = super::STATE.with(|s| {
+            process_due_tasks(s, 1001, || true);
+            s.tasks.borrow()[&1].dispute_deadline.unwrap()
+        });
+        assert_eq!(balance(&escrow_account(1)), 140);
+        let settled = super::STATE.with(|s| s.transactions.borrow().len());
+        super::STATE.with(|s| process_due_tasks(s, dispute_deadline + 1, || true));
+
+        // Half of the no vote's stake on the winning answer is slashed. The
+        // pool and the slashed 10 are split by stake between the yes votes on
+        // the winner, with the rounding remainder going to the first voter.
+        let from_escrow = |kind, recipient: Principal, amount| {
+            (
+                kind,
+                Some(escrow_account(1)),
+                Some(Account::from(recipient)),
+                amount,
+            )
+        };
+        assert_eq!(
+            movements(settled),
+            vec![
+                from_escrow(TransactionKind::Payout, principal2, 50),
+                from_escrow(TransactionKind::StakeRelease, principal3, 30),
+                from_escrow(TransactionKind::StakeRelease, principal5, 10),
+                from_escrow(TransactionKind::StakeRelease, principal6, 10),
+                from_escrow(TransactionKind::StakeRelease, principal7, 10),
+                from_escrow(TransactionKind::VoterReward, principal3, 23),
+                from_escrow(TransactionKind::VoterReward, principal5, 7),
+            ]
+        );
+        assert_eq!(balance(&Account::from(principal2)), 50);
+        assert_eq!(balance(&Account::from(principal3)), 123);
+        assert_eq!(balance(&Account::from(principal5)), 107);
+        assert_eq!(balance(&Account::from(principal6)), 90);
+        assert_eq!(balance(&Account::from(principal7)), 100);
+        assert_eq!(balance(&escrow_account(1)), 0);
+        super::STATE.with(|s| {
+            let tasks = s.tasks.borrow();
+            assert_eq!(
+                tasks[&1].voter_rewards,
+                vec![
+                    VoterReward {
+                        voter: principal3,
+                        amount: 23,
+                    },
+                    VoterReward {
+                        voter: principal5,
+                        amount: 7,
+                    },
+                ]
+            );
+            assert!(tasks[&1].payouts_released);
+        });
+    }
+
+    #[test]
+    fn test_quorum() {
+        let (principal1, principal2, _) = principals();
+        let vote = |voter| Vote {
+            voter,
+            choice: Choice::Yes,
+            weight: 1,
+            stake: 0,
+        };
+        let answer = |votes| Answer {
+            votes,
+            ..answer(principal1)
+        };
+        let answer1 = answer(vec![vote(principal2)]);
+        let answer2 = answer(vec![vote(principal2)]);
+        let quorum = |count| Quorum {
+            min_count: 2,
+            count,
+            fallback: QuorumFallback::ExtendReview,
+        };
+        // The same voter counts once towards a quorum of voters.
+        assert!(quorum(QuorumCount::Votes).is_met(&[(1, &answer1), (2, &answer2)]));
+        assert!(!quorum(QuorumCount::Voters).is_met(&[(1, &answer1), (2, &answer2)]));
+        assert!(!quorum(QuorumCount::Votes).is_met(&[(1, &answer1)]));
+        assert!(matches!(
+            Quorum {
+                min_count: 0,
+                count: QuorumCount::Votes,
+                fallback: QuorumFallback::Refund,
+            }
+            .validate(),
+            Err(ButlerError::InvalidOptions(_))
+        ));
+
+        // Below quorum, the review is extended once by its original length.
+        super::STATE.with(|s| {
+            s.answers.borrow_mut().insert(1, answer1.clone());
+            s.tasks.borrow_mut().insert(
+                1,
+                TaskInternal {
+                    status: TaskStatus::InReview,
+                    quorum: Some(quorum(QuorumCount::Votes)),
+                    ..task(principal1, &[1])
+                },
+            );
+            assert_eq!(settle_task(s, 1, 1001), Ok(()));
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&1].status, TaskStatus::InReview);
+            assert_eq!(tasks[&1].vote_deadline, 1800);
+            assert!(tasks[&1].review_extended);
+            assert_eq!(
+                pop_due_tasks(&mut s.deadlines.borrow_mut(), 1801, 10),
+                vec![1]
+            );
+        });
+
+        // Still below quorum after the extension, the reward is refunded.
+        super::STATE.with(|s| {
+            s.ledger.borrow_mut().insert(escrow_account(1), 50);
+            s.tasks.borrow_mut().get_mut(&1).unwrap().sponsors = vec![Sponsor {
+                sponsor: principal1,
+                amount: 50,
+            }];
+            assert_eq!(settle_task(s, 1, 1801), Ok(()));
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&1].status, TaskStatus::Closed);
+            assert_eq!(tasks[&1].close_reason, Some(CloseReason::QuorumNotReached));
+            assert_eq!(s.ledger.borrow().get(&Account::from(principal1)), Some(&50));
+        });
+
+        // Without a quorum, a task nobody voted on closes without payouts.
+        super::STATE.with(|s| {
+            s.answers.borrow_mut().insert(2, answer(vec![]));
+            s.tasks.borrow_mut().insert(
+                2,
+                TaskInternal {
+                    status: TaskStatus::InReview,
+                    ..task(principal1, &[2])
+                },
+            );
+            assert_eq!(settle_task(s, 2, 1001), Ok(()));
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&2].status, TaskStatus::Closed);
+            assert_eq!(tasks[&2].close_reason, Some(CloseReason::NoVotes));
+            assert!(tasks[&2].payouts.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_update_answer() {
+        let (principal1, principal2, _) = principals();
+        super::STATE.with(|s| {
+            s.answers.borrow_mut().insert(1, answer(principal1));
+            s.answer_tasks.borrow_mut().insert(1, 1);
+            s.tasks.borrow_mut().insert(
+                1,
+                TaskInternal {
+                    answer_deadline: 1000,
+                    vote_deadline: 2000,
+                    ..task(principal2, &[1])
+                },
+            );
+        });
+
+        assert_eq!(
+            update_answer_impl(principal2, 500, 1, ByteBuf::from(vec![2])),
+            Err(ButlerError::NotAnswerAuthor(1))
+        );
+        assert_eq!(
+            update_answer_impl(
+                principal1,
+                500,
+                1,
+                ByteBuf::from(vec![0; MAX_CONTENT_SIZE + 1])
+            ),
+            Err(ButlerError::PayloadTooLarge {
+                max_size: MAX_CONTENT_SIZE as u64,
+                size: MAX_CONTENT_SIZE as u64 + 1
+            })
+        );
+        assert_eq!(
+            update_answer_impl(principal1, 500, 1, ByteBuf::from(vec![2])),
+            Ok(())
+        );
+        assert_eq!(
+            update_answer_impl(principal1, 600, 1, ByteBuf::from(vec![3])),
+            Ok(())
+        );
+        assert_eq!(
+            update_answer_impl(principal1, 1001, 1, ByteBuf::from(vec![4])),
+            Err(ButlerError::DeadlinePassed { deadline: 1000 })
+        );
+        super::STATE.with(|s| {
+            let answers = s.answers.borrow();
+            assert_eq!(answers[&1].content, ByteBuf::from(vec![3]));
+            assert_eq!(
+                answers[&1].revisions,
+                vec![
+                    Revision {
+                        content: ByteBuf::from(vec![1]),
+                        replaced_at: 500,
+                    },
+                    Revision {
+                        content: ByteBuf::from(vec![2]),
+                        replaced_at: 600,
+                    },
+                ]
+            );
+        });
+
+        // Withdrawing an answer frees its slot.
+        assert_eq!(
+            withdraw_answer_impl(principal2, 700, 1),
+            Err(ButlerError::NotAnswerAuthor(1))
+        );
+        assert_eq!(withdraw_answer_impl(principal1, 700, 1), Ok(()));
+        assert_eq!(
+            withdraw_answer_impl(principal1, 700, 1),
+            Err(ButlerError::AnswerNotFound(1))
+        );
+        super::STATE.with(|s| {
+            assert!(s.tasks.borrow()[&1].answers.is_empty());
+            assert!(s.answer_tasks.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_reputation() {
+        let (principal1, principal2, principal3) = principals();
+
+        let ranked = vec![
+            RankedAnswer {
+                answer_id: 1,
+                submitter: principal1,
+                net_votes: 2,
+            },
+            RankedAnswer {
+                answer_id: 2,
+                submitter: principal2,
+                net_votes: -1,
+            },
+        ];
+        let payouts = vec![Payout {
+            answer_id: 1,
+            recipient: principal1,
+            amount: 10,
+        }];
+        let vote = |voter, choice| Vote {
+            voter,
+            choice,
+            weight: 1,
+            stake: 0,
+        };
+        let winning_votes = vec![vote(principal2, Choice::Yes), vote(principal3, Choice::No)];
+
+        let mut reputation = HashMap::new();
+        reputation.insert(principal3, 3);
+        update_reputation(&mut reputation, &ranked, &payouts, &winning_votes);
+
+        assert_eq!(reputation.get(&principal1), Some(&REPUTATION_FOR_PAYOUT));
+        assert_eq!(
+            reputation.get(&principal2),
+            Some(&(REPUTATION_FOR_WINNING_VOTE - REPUTATION_FOR_REJECTED_ANSWER))
+        );
+        assert_eq!(reputation.get(&principal3), Some(&3));
+
+        // Only answer authors build up skills.
+        let task_skills = vec![
+            Skill::TaskType(TaskType::TranslateText),
+            Skill::Language(Language::German),
+        ];
+        let mut skills = HashMap::new();
+        update_skills(&mut skills, &task_skills, &ranked, &payouts);
+        assert_eq!(
+            skills[&principal1].get(&task_skills[1]),
+            Some(&REPUTATION_FOR_PAYOUT)
+        );
+        assert_eq!(
+            skills[&principal2].get(&task_skills[0]),
+            Some(&-REPUTATION_FOR_REJECTED_ANSWER)
+        );
+        assert_eq!(skills.get(&principal3), None);
+
+        assert_eq!(
+            leaderboard(&reputation, 2),
+            vec![
+                ReputationEntry {
+                    principal: principal1,
+                    reputation: 10,
+                },
+                ReputationEntry {
+                    principal: principal3,
+                    reputation: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_commit_reveal() {
+        let (principal1, principal2, principal3) = principals();
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            ledger.insert(Account::from(principal1), 100);
+            ledger.insert(Account::from(principal2), 100);
+            ledger.insert(Account::from(principal3), 100);
+            s.answers.borrow_mut().insert(1, answer(principal1));
+            s.tasks.borrow_mut().insert(
+                1,
+                TaskInternal {
+                    sponsors: vec![Sponsor {
+                        sponsor: principal1,
+                        amount: 50,
+                    }],
+                    commit_reveal: Some(CommitReveal {
+                        reveal_period: 100,
+                        penalty: 0,
+                    }),
+                    ..task(principal1, &[1])
+                },
+            );
+        });
+
+        let salt = ByteBuf::from(vec![4, 2]);
+        let sealed = |voter, choice| {
+            Ballot::Commitment(ByteBuf::from(commitment_hash(&voter, &choice, &salt)))
+        };
+
+        // Votes must be sealed.
+        assert!(matches!(
+            vote_impl(principal2, 500, 1, 1, Ballot::Choice(Choice::Yes), None),
+            Err(ButlerError::InvalidBallot(_))
+        ));
+        assert_eq!(
+            vote_impl(principal2, 500, 1, 1, sealed(principal2, Choice::Yes), None),
+            Ok(())
+        );
+        assert_eq!(
+            vote_impl(principal3, 500, 1, 1, sealed(principal3, Choice::No), None),
+            Ok(())
+        );
+        assert_eq!(
+            vote_impl(principal2, 600, 1, 1, sealed(principal2, Choice::No), None),
+            Err(ButlerError::AlreadyVoted(1))
+        );
+
+        // Votes are revealed after the deadline.
+        assert_eq!(
+            reveal_vote_impl(principal2, 900, 1, 1, Choice::Yes, salt.clone()),
+            Err(ButlerError::RevealNotOpen { opens_at: 1000 })
+        );
+        assert_eq!(
+            reveal_vote_impl(principal2, 1050, 1, 1, Choice::No, salt.clone()),
+            Err(ButlerError::CommitmentMismatch(1))
+        );
+        assert_eq!(
+            reveal_vote_impl(principal2, 1050, 1, 1, Choice::Yes, salt.clone()),
+            Ok(())
+        );
+        assert_eq!(
+            reveal_vote_impl(principal2, 1050, 1, 1, Choice::Yes, salt.clone()),
+            Err(ButlerError::CommitmentNotFound(1))
+        );
+        assert_eq!(
+            reveal_vote_impl(principal3, 1101, 1, 1, Choice::No, salt.clone()),
+            Err(ButlerError::DeadlinePassed { deadline: 1100 })
+        );
+
+        // Only the revealed vote counts.
+        super::STATE.with(|s| {
+            let answers = s.answers.borrow();
+            let answer = &answers[&1];
+            assert_eq!(answer.commitments.len(), 1);
+            assert_eq!(rank_answers(vec![(1, answer)])[0].net_votes, 1);
+            assert_eq!(s.tasks.borrow()[&1].settlement_time(), 1100);
+        });
+    }
 
-                // If votes exist, then the top voted answer gets the reward.
-                // Otherwise, it's returned to the task submitter.
-                let reward_principal = if votes_exist {
-                    submitter_for_top_voted_answer
-                } else {
-                    task.submitter
-                };
-                let mut ledger = s.ledger.borrow_mut();
-                match ledger.get_mut(&reward_principal) {
-                    Some(amount) => { *amount += task.reward; },
-                    // This should not happen, but handle it just in case.
-                    None => {ic_cdk::trap(&format!("Principal {} is not registered.", reward_principal));}
-                }
+    #[test]
+    fn test_conflict_of_interest() {
+        let (principal1, principal2, principal3) = principals();
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            for principal in &[principal1, principal2, principal3] {
+                ledger.insert(Account::from(*principal), 100);
             }
-        }
-    });
-}
+            let mut answers = s.answers.borrow_mut();
+            answers.insert(1, answer(principal2));
+            answers.insert(2, answer(principal3));
+            s.tasks.borrow_mut().insert(1, task(principal1, &[1, 2]));
+        });
+        let set_conflict_of_interest = |exclude_answerers, exclude_submitter| {
+            super::STATE.with(|s| {
+                s.tasks
+                    .borrow_mut()
+                    .get_mut(&1)
+                    .unwrap()
+                    .conflict_of_interest = ConflictOfInterest {
+                    exclude_answerers,
+                    exclude_submitter,
+                };
+            })
+        };
+        let yes = || Ballot::Choice(Choice::Yes);
 
-fn main() {}
+        // Nobody can vote on their own answer, but by default answerers can
+        // vote on other answers and the submitter on all of them.
+        assert_eq!(
+            vote_impl(principal2, 500, 1, 1, yes(), None),
+            Err(ButlerError::OwnAnswer(1))
+        );
+        assert_eq!(vote_impl(principal1, 500, 1, 1, yes(), None), Ok(()));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        set_conflict_of_interest(true, false);
+        assert_eq!(
+            vote_impl(principal2, 500, 2, 1, yes(), None),
+            Err(ButlerError::AnswererCannotVote(1))
+        );
+        assert_eq!(
+            vote_impl(principal3, 500, 1, 1, yes(), None),
+            Err(ButlerError::AnswererCannotVote(1))
+        );
 
-    #[test]
-    fn test_get_task() {
-        let principal1 =
-            Principal::from_text("ollzc-44u63-k7twc-5fffp-f5vr7-ptyb5-v6esl-cswqt-f4u4t-qfxbj-6ae")
-                .unwrap();
-        let principal2 =
-            Principal::from_text("etn6u-fsyqb-qmjjs-murc2-w4qtf-4tota-tp2vi-y5jau-kavt5-zi6dj-lae")
-                .unwrap();
-        let principal3 =
-            Principal::from_text("lv3pe-37kt2-3kbhe-r2oyg-ppn4z-mftmh-j242j-gu7ps-hvun5-3ioxb-3qe")
-                .unwrap();
+        set_conflict_of_interest(false, true);
+        assert_eq!(
+            vote_impl(principal1, 500, 2, 1, yes(), None),
+            Err(ButlerError::SubmitterCannotVote(1))
+        );
+        assert_eq!(vote_impl(principal2, 500, 2, 1, yes(), None), Ok(()));
+    }
 
-        let bytes: [u8; 7] = [65, 66, 67, 68, 69, 70, 71];
-        let mut v = Vec::new();
-        v.push(Vote {
-            voter: Principal::anonymous(),
-            choice: Choice::Yes,
+    #[test]
+    fn test_change_vote() {
+        let (principal1, principal2, principal3) = principals();
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            for principal in &[principal1, principal2, principal3] {
+                ledger.insert(Account::from(*principal), 100);
+            }
+            let mut answers = s.answers.borrow_mut();
+            answers.insert(1, answer(principal2));
+            answers.insert(2, answer(principal2));
+            let mut tasks = s.tasks.borrow_mut();
+            tasks.insert(1, task(principal1, &[1]));
+            tasks.insert(
+                2,
+                TaskInternal {
+                    commit_reveal: Some(CommitReveal {
+                        reveal_period: 100,
+                        penalty: 0,
+                    }),
+                    ..task(principal1, &[2])
+                },
+            );
         });
-        v.push(Vote {
-            voter: Principal::anonymous(),
-            choice: Choice::Yes,
+        let choice = |choice| Ballot::Choice(choice);
+
+        // Only registered principals can change or retract votes.
+        let unregistered = Principal::from_slice(&[4]);
+        assert_eq!(
+            change_vote_impl(unregistered, 600, 1, 1, choice(Choice::No)),
+            Err(ButlerError::NotRegistered)
+        );
+        assert_eq!(
+            retract_vote_impl(unregistered, 600, 1, 1),
+            Err(ButlerError::NotRegistered)
+        );
+
+        // Votes can be changed or retracted while voting is open, and every
+        // change is recorded.
+        assert_eq!(
+            vote_impl(principal3, 500, 1, 1, choice(Choice::Yes), None),
+            Ok(())
+        );
+        assert_eq!(
+            change_vote_impl(principal1, 600, 1, 1, choice(Choice::No)),
+            Err(ButlerError::VoteNotFound(1))
+        );
+        assert_eq!(
+            change_vote_impl(principal3, 600, 1, 1, choice(Choice::No)),
+            Ok(())
+        );
+        assert_eq!(retract_vote_impl(principal3, 700, 1, 1), Ok(()));
+        assert_eq!(
+            retract_vote_impl(principal3, 700, 1, 1),
+            Err(ButlerError::VoteNotFound(1))
+        );
+        assert_eq!(
+            vote_impl(principal3, 800, 1, 1, choice(Choice::Yes), None),
+            Ok(())
+        );
+        assert_eq!(
+            change_vote_impl(principal3, 1001, 1, 1, choice(Choice::No)),
+            Err(ButlerError::DeadlinePassed { deadline: 1000 })
+        );
+        assert_eq!(
+            retract_vote_impl(principal3, 1001, 1, 1),
+            Err(ButlerError::DeadlinePassed { deadline: 1000 })
+        );
+        super::STATE.with(|s| {
+            let answers = s.answers.borrow();
+            assert_eq!(answers[&1].votes.len(), 1);
+            assert_eq!(answers[&1].votes[0].choice, Choice::Yes);
+            assert_eq!(
+                answers[&1].vote_changes,
+                vec![
+                    VoteChange {
+                        voter: principal3,
+                        previous: choice(Choice::Yes),
+                        current: Some(choice(Choice::No)),
+                        timestamp: 600,
+                    },
+                    VoteChange {
+                        voter: principal3,
+                        previous: choice(Choice::No),
+                        current: None,
+                        timestamp: 700,
+                    },
+                ]
+            );
         });
 
-        let mut answers_map = HashMap::new();
-        let id = 1;
-        answers_map.insert(
-            id,
-            Answer {
-                submitter: principal1,
-                submission_time: 1631075074,
-                content: ByteBuf::from(bytes),
-                votes: v.clone(),
-            },
+        // Sealed votes can only be changed to other sealed votes.
+        let salt = ByteBuf::from(vec![4, 2]);
+        let sealed = |choice| {
+            Ballot::Commitment(ByteBuf::from(commitment_hash(&principal3, &choice, &salt)))
+        };
+        assert_eq!(
+            vote_impl(principal3, 500, 2, 2, sealed(Choice::No), None),
+            Ok(())
         );
-        answers_map.insert(
-            id + 1,
-            Answer {
-                submitter: principal1,
-                submission_time: 1631075073,
-                content: ByteBuf::from(bytes),
-                votes: v.clone(),
-            },
+        assert!(matches!(
+            change_vote_impl(principal3, 600, 2, 2, choice(Choice::Yes)),
+            Err(ButlerError::InvalidBallot(_))
+        ));
+        assert_eq!(
+            change_vote_impl(principal3, 600, 2, 2, sealed(Choice::Yes)),
+            Ok(())
         );
-        answers_map.insert(
-            id + 2,
+        super::STATE.with(|s| {
+            let answers = s.answers.borrow();
+            assert_eq!(
+                answers[&2].commitments[0].hash,
+                commitment_hash(&principal3, &Choice::Yes, &salt)
+            );
+            assert_eq!(answers[&2].vote_changes[0].previous, sealed(Choice::No));
+        });
+    }
+
+    #[test]
+    fn test_summary() {
+        let (principal1, principal2, principal3) = principals();
+        let input = "ab".repeat(PAYLOAD_PREVIEW_LENGTH);
+        let payload = Encode!(&TranslateTextInput {
+            input: input.clone(),
+            language: Language::French,
+        })
+        .unwrap();
+        let mut task = TaskInternal {
+            payload: ByteBuf::from(payload),
+            language: Some(Language::French),
+            status: TaskStatus::InReview,
+            ..task(principal1, &[1, 2])
+        };
+        let mut answers = HashMap::new();
+        answers.insert(
+            1,
             Answer {
-                submitter: principal2,
-                submission_time: 1631075074,
-                content: ByteBuf::from(bytes),
-                votes: v.clone(),
+                votes: vec![Vote {
+                    voter: principal3,
+                    choice: Choice::Yes,
+                    weight: 1,
+                    stake: 0,
+                }],
+                commitments: vec![Commitment {
+                    voter: principal1,
+                    hash: ByteBuf::from(vec![0; 32]),
+                    weight: 1,
+                    stake: 0,
+                    deposit: 0,
+                }],
+                ..answer(principal2)
             },
         );
-        answers_map.insert(
-            id + 3,
-            Answer {
-                submitter: principal2,
-                submission_time: 1631075073,
-                content: ByteBuf::from(bytes),
-                votes: v.clone(),
-            },
+        answers.insert(2, answer(principal3));
+
+        assert_eq!(
+            task.summary(7, &answers),
+            ShortTask {
+                id: 7,
+                submitter: principal1,
+                task_type: TaskType::TranslateText,
+                status: TaskStatus::InReview,
+                reward: 50,
+                answer_deadline: 200,
+                vote_deadline: 1000,
+                answer_count: 2,
+                total_votes: 2,
+                language: Some(Language::French),
+                payload_preview: input[..PAYLOAD_PREVIEW_LENGTH].to_string(),
+            }
         );
 
-        //let mut tasks = HashMap::new();
-        let mut ans_ids: HashSet<u64> = HashSet::new();
-        ans_ids.insert(1);
-        ans_ids.insert(2);
-        ans_ids.insert(3);
-        ans_ids.insert(4);
+        // Sealed votes that were not revealed by settlement no longer count.
+        task.status = TaskStatus::Closed;
+        assert_eq!(task.summary(7, &answers).total_votes, 1);
+
+        // An undecodable payload only leaves the preview empty.
+        task.payload = ByteBuf::from(vec![1, 2, 3]);
+        assert_eq!(task.summary(7, &answers).payload_preview, "");
+    }
 
+    #[test]
+    fn test_disputes() {
+        let (principal1, principal2, principal3) = principals();
+        let principal4 = Principal::from_slice(&[4]);
+        let principal5 = Principal::from_slice(&[5]);
         super::STATE.with(|s| {
-            //s.tasks = RefCell::new(tasks.clone());
-            //s.answers = RefCell::new(answers_map.clone());
-            //s.ledger = RefCell::new(ledger.clone());
             let mut ledger = s.ledger.borrow_mut();
-            ledger.insert(principal1, 100);
-            ledger.insert(principal2, 200);
-
-            let mut answers_map = s.answers.borrow_mut();
-            answers_map.insert(
-                id,
+            ledger.insert(Account::from(principal1), 100);
+            ledger.insert(Account::from(principal2), 5);
+            ledger.insert(Account::from(principal3), 100);
+            s.answers.borrow_mut().insert(
+                1,
                 Answer {
-                    submitter: principal1,
-                    submission_time: 1631075074,
-                    content: ByteBuf::from(bytes),
-                    votes: v.clone(),
+                    votes: vec![Vote {
+                        voter: principal3,
+                        choice: Choice::Yes,
+                        weight: 1,
+                        stake: 0,
+                    }],
+                    ..answer(principal2)
                 },
             );
-            answers_map.insert(
-                id + 1,
-                Answer {
-                    submitter: principal1,
-                    submission_time: 1631075073,
-                    content: ByteBuf::from(bytes),
-                    votes: v.clone(),
+            s.tasks.borrow_mut().insert(
+                1,
+                TaskInternal {
+                    status: TaskStatus::Closed,
+                    sponsors: vec![Sponsor {
+                        sponsor: principal1,
+                        amount: 50,
+                    }],
+                    dispute_deadline: Some(2000),
+                    ..task(principal1, &[1])
                 },
             );
-            answers_map.insert(
-                id + 2,
-                Answer {
-                    submitter: principal2,
-                    submission_time: 1631075074,
-                    content: ByteBuf::from(bytes),
-                    votes: v.clone(),
-                },
+            // Participants cannot be jurors, however high their reputation.
+            s.reputation.borrow_mut().insert(principal3, 100);
+        });
+
+        super::STATE.with(|s| {
+            assert_eq!(
+                dispute_candidates(s, principal3, 1500, 1, ""),
+                Err(ButlerError::NotParticipant(1))
             );
-            answers_map.insert(
-                id + 3,
-                Answer {
-                    submitter: principal2,
-                    submission_time: 1631075073,
-                    content: ByteBuf::from(bytes),
-                    votes: v.clone(),
-                },
+            assert_eq!(
+                dispute_candidates(s, principal1, 2001, 1, ""),
+                Err(ButlerError::DeadlinePassed { deadline: 2000 })
+            );
+            assert_eq!(
+                dispute_candidates(s, principal2, 1500, 1, ""),
+                Err(ButlerError::InsufficientBalance {
+                    balance: 5,
+                    required: DISPUTE_BOND
+                })
+            );
+            assert_eq!(
+                dispute_candidates(s, principal1, 1500, 1, ""),
+                Err(ButlerError::NoJurorsAvailable)
             );
+            let mut reputation = s.reputation.borrow_mut();
+            reputation.insert(principal4, MIN_JUROR_REPUTATION);
+            reputation.insert(principal5, MIN_JUROR_REPUTATION - 1);
+        });
+        super::STATE.with(|s| {
+            assert_eq!(
+                dispute_candidates(s, principal1, 1500, 1, ""),
+                Ok(vec![principal4])
+            );
+        });
 
+        // The jury only depends on the seed and the candidates.
+        let candidates: Vec<Principal> = (0..10).map(|i| Principal::from_slice(&[i])).collect();
+        let jury = select_jury(candidates.clone(), &[1, 2, 3], JURY_SIZE);
+        assert_eq!(jury.len(), JURY_SIZE);
+        assert_eq!(jury.iter().collect::<HashSet<_>>().len(), JURY_SIZE);
+        assert_eq!(select_jury(candidates.clone(), &[1, 2, 3], JURY_SIZE), jury);
+        assert_ne!(select_jury(candidates, &[3, 2, 1], JURY_SIZE), jury);
+        assert_eq!(
+            select_jury(vec![principal4], &[1, 2, 3], JURY_SIZE),
+            vec![principal4]
+        );
+
+        let verdicts = |verdicts: Vec<Verdict>| -> Vec<JurorVerdict> {
+            verdicts
+                .into_iter()
+                .map(|verdict| JurorVerdict {
+                    juror: principal4,
+                    verdict,
+                })
+                .collect()
+        };
+        assert_eq!(tally_verdicts(&[]), Verdict::Uphold);
+        assert_eq!(
+            tally_verdicts(&verdicts(vec![
+                Verdict::Award(1),
+                Verdict::Award(1),
+                Verdict::Uphold
+            ])),
+            Verdict::Award(1)
+        );
+        assert_eq!(
+            tally_verdicts(&verdicts(vec![Verdict::Award(1), Verdict::Uphold])),
+            Verdict::Uphold
+        );
+        assert_eq!(
+            tally_verdicts(&verdicts(vec![Verdict::Award(1), Verdict::Award(2)])),
+            Verdict::Uphold
+        );
+
+        assert_eq!(
+            cast_verdict_impl(principal4, 1500, 1, Verdict::Uphold),
+            Err(ButlerError::NoOpenDispute(1))
+        );
+        super::STATE.with(|s| {
             let mut tasks = s.tasks.borrow_mut();
-            tasks.insert(
-                id,
-                TaskInternal {
-                    submitter: principal1,
-                    task_type: TaskType::TranslateText,
-                    payload: ByteBuf::from(bytes),
-                    deadline: 1631075080,
-                    reward: 12,
-                    answers: ans_ids.clone(),
-                    status: TaskStatus::Open,
-                },
-            );
-            tasks.insert(
-                id + 1,
-                TaskInternal {
-                    submitter: principal1,
-                    task_type: TaskType::TranslateText,
-                    payload: ByteBuf::from(bytes),
-                    deadline: 1631075083,
-                    reward: 11,
-                    answers: ans_ids.clone(),
-                    status: TaskStatus::Open,
+            let task = tasks.get_mut(&1).unwrap();
+            task.status = TaskStatus::Disputed;
+            task.dispute = Some(Dispute {
+                opened_by: principal1,
+                reason: String::new(),
+                bond: DISPUTE_BOND,
+                opened_at: 1500,
+                jury: vec![principal4, principal5],
+                verdicts: vec![],
+                deadline: 3000,
+                outcome: None,
+            });
+        });
+        assert_eq!(
+            cast_verdict_impl(principal1, 1600, 1, Verdict::Uphold),
+            Err(ButlerError::NotJuror(1))
+        );
+        assert_eq!(
+            cast_verdict_impl(principal4, 1600, 1, Verdict::Award(2)),
+            Err(ButlerError::AnswerNotInTask {
+                answer_id: 2,
+                task_id: 1
+            })
+        );
+        assert_eq!(
+            cast_verdict_impl(principal4, 3001, 1, Verdict::Uphold),
+            Err(ButlerError::DeadlinePassed { deadline: 3000 })
+        );
+        assert_eq!(
+            cast_verdict_impl(principal4, 1600, 1, Verdict::Award(1)),
+            Ok(())
+        );
+        assert_eq!(
+            cast_verdict_impl(principal4, 1700, 1, Verdict::Uphold),
+            Err(ButlerError::VerdictAlreadyCast(1))
+        );
+    }
+
+    // Sets up task 1, settled by votes for the answer of principal 2 and
+    // disputed by principal 4, the author of the other answer. Returns the
+    // jurors in the order they were drawn.
+    fn open_test_dispute() -> Vec<Principal> {
+        let (principal1, principal2, principal3) = principals();
+        let principal4 = Principal::from_slice(&[4]);
+        super::STATE.with(|s| {
+            let mut ledger = s.ledger.borrow_mut();
+            for principal in &[principal1, principal2, principal3] {
+                ledger.insert(Account::from(*principal), 0);
+            }
+            ledger.insert(Account::from(principal4), 20);
+            ledger.insert(escrow_account(1), 50);
+            let mut answers = s.answers.borrow_mut();
+            answers.insert(
+                1,
+                Answer {
+                    votes: vec![Vote {
+                        voter: principal3,
+                        choice: Choice::Yes,
+                        weight: 1,
+                        stake: 0,
+                    }],
+                    ..answer(principal2)
                 },
             );
-            tasks.insert(
-                id + 2,
+            answers.insert(2, answer(principal4));
+            s.tasks.borrow_mut().insert(
+                1,
                 TaskInternal {
-                    submitter: principal2,
-                    task_type: TaskType::TranslateText,
-                    payload: ByteBuf::from(bytes),
-                    deadline: 1631075085,
-                    reward: 10,
-                    answers: ans_ids.clone(),
-                    status: TaskStatus::Open,
+                    status: TaskStatus::Closed,
+                    close_reason: Some(CloseReason::Votes),
+                    sponsors: vec![Sponsor {
+                        sponsor: principal1,
+                        amount: 50,
+                    }],
+                    payouts: vec![Payout {
+                        answer_id: 1,
+                        recipient: principal2,
+                        amount: 50,
+                    }],
+                    dispute_deadline: Some(2000),
+                    ..task(principal1, &[1, 2])
                 },
             );
+            schedule(&mut s.deadlines.borrow_mut(), 2000, 1);
+            let mut reputation = s.reputation.borrow_mut();
+            for juror in 10..13 {
+                reputation.insert(Principal::from_slice(&[juror]), MIN_JUROR_REPUTATION);
+            }
         });
 
-        // Request from unregistered principal
-        let result = std::panic::catch_unwind(|| get_task_impl(principal3, 1));
-        assert!(result.is_err());
+        assert_eq!(
+            open_dispute_impl(principal4, 1500, 1, String::new(), &[1, 2, 3]),
+            Ok(())
+        );
+        // The bond is locked in the task's escrow along with the reward.
+        assert_eq!(
+            movements(0),
+            vec![(
+                TransactionKind::DisputeBond,
+                Some(Account::from(principal4)),
+                Some(escrow_account(1)),
+                DISPUTE_BOND
+            )]
+        );
+        assert_eq!(balance(&Account::from(principal4)), 20 - DISPUTE_BOND);
+        assert_eq!(balance(&escrow_account(1)), 50 + DISPUTE_BOND);
+        super::STATE.with(|s| {
+            let tasks = s.tasks.borrow();
+            let dispute = tasks[&1].dispute.as_ref().unwrap();
+            assert_eq!(tasks[&1].status, TaskStatus::Disputed);
+            assert_eq!(
+                s.deadlines.borrow().keys().collect::<Vec<_>>(),
+                vec![&dispute.deadline]
+            );
+            assert_eq!(dispute.jury.len(), 3);
+            dispute.jury.clone()
+        })
+    }
 
-        // Request for valid task
-        let mut answers = Vec::new();
-        for ans_id in ans_ids {
-            if let Some(ans_ref) = answers_map.get(&ans_id) {
-                answers.push(AnswerWithId{
-                    answer_id: ans_id,
-                    answer: ans_ref.clone(),
-                });
-            }
-        }
-        let expected_result = Task {
-            submitter: principal1,
-            task_type: TaskType::TranslateText,
-            payload: ByteBuf::from(bytes),
-            deadline: 1631075080,
-            reward: 12,
-            answers,
-            status: TaskStatus::Open,
+    #[test]
+    fn test_dispute_upheld() {
+        let (_, principal2, _) = principals();
+        let principal4 = Principal::from_slice(&[4]);
+        let jury = open_test_dispute();
+
+        assert_eq!(cast_verdict_impl(jury[0], 1600, 1, Verdict::Uphold), Ok(()));
+        assert_eq!(
+            cast_verdict_impl(jury[1], 1600, 1, Verdict::Award(2)),
+            Ok(())
+        );
+        assert_eq!(cast_verdict_impl(jury[2], 1600, 1, Verdict::Uphold), Ok(()));
+
+        // The bond is shared among the jurors, with the remainder going to
+        // the first one, and the settled payouts are released.
+        let juror_reward = |juror: Principal, amount| {
+            (
+                TransactionKind::JurorReward,
+                Some(escrow_account(1)),
+                Some(Account::from(juror)),
+                amount,
+            )
         };
-        let result = get_task_impl(principal1, 1);
-        assert_eq!(result, expected_result);
+        assert_eq!(
+            movements(1),
+            vec![
+                juror_reward(jury[0], 4),
+                juror_reward(jury[1], 3),
+                juror_reward(jury[2], 3),
+                (
+                    TransactionKind::Payout,
+                    Some(escrow_account(1)),
+                    Some(Account::from(principal2)),
+                    50
+                ),
+            ]
+        );
+        assert_eq!(balance(&Account::from(jury[0])), 4);
+        assert_eq!(balance(&Account::from(jury[1])), 3);
+        assert_eq!(balance(&Account::from(jury[2])), 3);
+        assert_eq!(balance(&Account::from(principal2)), 50);
+        assert_eq!(balance(&Account::from(principal4)), 20 - DISPUTE_BOND);
+        assert_eq!(balance(&escrow_account(1)), 0);
+        super::STATE.with(|s| {
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&1].status, TaskStatus::Closed);
+            assert_eq!(
+                tasks[&1].dispute.as_ref().unwrap().outcome,
+                Some(Verdict::Uphold)
+            );
+            assert!(tasks[&1].payouts_released);
+            assert!(s.deadlines.borrow().is_empty());
+        });
+    }
 
-        // Request for invalid task
-        let result = std::panic::catch_unwind(|| get_task_impl(principal3, 10));
-        assert!(result.is_err());
+    #[test]
+    fn test_dispute_awarded() {
+        let (_, principal2, _) = principals();
+        let principal4 = Principal::from_slice(&[4]);
+        let jury = open_test_dispute();
+
+        assert_eq!(
+            cast_verdict_impl(jury[0], 1600, 1, Verdict::Award(2)),
+            Ok(())
+        );
+        assert_eq!(
+            cast_verdict_impl(jury[1], 1600, 1, Verdict::Award(2)),
+            Ok(())
+        );
+
+        // The last juror does not deliver a verdict, so the dispute is
+        // resolved by the heartbeat once the jury deadline has passed.
+        let deadline =
+            super::STATE.with(|s| s.tasks.borrow()[&1].dispute.as_ref().unwrap().deadline);
+        super::STATE.with(|s| process_due_tasks(s, deadline, || true));
+        assert_eq!(movements(1), vec![]);
+        super::STATE.with(|s| process_due_tasks(s, deadline + 1, || true));
+
+        // The bond is refunded and the whole reward goes to the awarded
+        // answer instead.
+        assert_eq!(
+            movements(1),
+            vec![
+                (
+                    TransactionKind::BondRefund,
+                    Some(escrow_account(1)),
+                    Some(Account::from(principal4)),
+                    DISPUTE_BOND
+                ),
+                (
+                    TransactionKind::Payout,
+                    Some(escrow_account(1)),
+                    Some(Account::from(principal4)),
+                    50
+                ),
+            ]
+        );
+        assert_eq!(balance(&Account::from(principal4)), 20 + 50);
+        assert_eq!(balance(&Account::from(principal2)), 0);
+        assert_eq!(balance(&Account::from(jury[0])), 0);
+        assert_eq!(balance(&escrow_account(1)), 0);
+        super::STATE.with(|s| {
+            let tasks = s.tasks.borrow();
+            assert_eq!(tasks[&1].status, TaskStatus::Closed);
+            assert_eq!(
+                tasks[&1].dispute.as_ref().unwrap().outcome,
+                Some(Verdict::Award(2))
+            );
+            assert_eq!(
+                tasks[&1].payouts,
+                vec![Payout {
+                    answer_id: 2,
+                    recipient: principal4,
+                    amount: 50,
+                }]
+            );
+            assert!(tasks[&1].payouts_released);
+            assert!(s.deadlines.borrow().is_empty());
+        });
     }
 }